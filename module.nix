@@ -41,10 +41,39 @@ let
     {
       options = {
         port = lib.mkOption {
-          type = lib.types.port;
+          type = lib.types.nullOr lib.types.port;
+          default = null;
           description = "Port of the service";
         };
 
+        upstream = lib.mkOption {
+          type = lib.types.nullOr lib.types.str;
+          default = null;
+          description = "Address of a service on another machine, instead of `port`";
+          example = "192.168.1.20:8123";
+        };
+
+        socket = lib.mkOption {
+          type = lib.types.nullOr lib.types.str;
+          default = null;
+          description = "Unix socket that the service listens on, instead of `port`";
+          example = "/run/app.sock";
+        };
+
+        static = lib.mkOption {
+          type = lib.types.nullOr lib.types.str;
+          default = null;
+          description = "Directory of static files that incipit serves itself, instead of `port`";
+          example = "/var/www/blog";
+        };
+
+        fastcgiRoot = lib.mkOption {
+          type = lib.types.nullOr lib.types.str;
+          default = null;
+          description = "Document root of a PHP application, to speak FastCGI to `port` or `socket` (such as php-fpm)";
+          example = "/var/www/wiki";
+        };
+
         host = lib.mkOption {
           type = lib.types.str;
           description = "Hostname of the service";
@@ -74,10 +103,17 @@ in
       description = "Hostname of the address for the incipit dashboard";
     };
 
+    default-service = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "Service that gets the requests for unknown hosts";
+      example = "landing";
+    };
+
     services = lib.mkOption {
       type = lib.types.attrsOf (lib.types.submodule serviceOpts);
-      default = "0.0.0.0";
-      description = "Address to listen on";
+      default = { };
+      description = "Services to run, by name";
     };
   };
 
@@ -96,15 +132,33 @@ in
     };
 
     environment = {
-      "INCIPIT_INPCIPIT_HOST" = cfg.incipit-host;
+      "INCIPIT_INCIPIT_HOST" = cfg.incipit-host;
       "INCIPIT_ADDR" = cfg.addr;
       "INCIPIT_PORT" = "${toString cfg.port}";
-      "INCIPIT_SERVICE" =
-        let
-          mapService = name: service: ''"${name}"={ port=${builtins.toString service.port}, host="${service.host}" }'';
-          services = lib.mapAttrsToList mapService cfg.services;
-        in
-        "{ ${lib.concatStringsSep "," services} }";
-    };
+    }
+    // lib.optionalAttrs (cfg.default-service != null) {
+      "INCIPIT_DEFAULT_SERVICE" = cfg.default-service;
+    }
+    // lib.concatMapAttrs (
+      name: service:
+      {
+        "INCIPIT_SERVICE__${lib.toUpper name}__HOST" = service.host;
+      }
+      // lib.optionalAttrs (service.port != null) {
+        "INCIPIT_SERVICE__${lib.toUpper name}__PORT" = "${toString service.port}";
+      }
+      // lib.optionalAttrs (service.upstream != null) {
+        "INCIPIT_SERVICE__${lib.toUpper name}__UPSTREAM" = service.upstream;
+      }
+      // lib.optionalAttrs (service.socket != null) {
+        "INCIPIT_SERVICE__${lib.toUpper name}__SOCKET" = service.socket;
+      }
+      // lib.optionalAttrs (service.static != null) {
+        "INCIPIT_SERVICE__${lib.toUpper name}__STATIC__DIR" = service.static;
+      }
+      // lib.optionalAttrs (service.fastcgiRoot != null) {
+        "INCIPIT_SERVICE__${lib.toUpper name}__FASTCGI__ROOT" = service.fastcgiRoot;
+      }
+    ) cfg.services;
   };
 }
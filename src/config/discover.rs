@@ -0,0 +1,77 @@
+//! Finding the config file.
+
+use std::path::PathBuf;
+
+use color_eyre::eyre;
+
+/// Environment variable with the path to the config file.
+const CONFIG_VAR: &str = "INCIPIT_CONFIG";
+
+/// Supported extensions of config files, in order of preference.
+const EXTENSIONS: [&str; 4] = ["toml", "yaml", "yml", "json"];
+
+/// See [`super::Config::discover`].
+pub(super) fn discover() -> eyre::Result<Option<PathBuf>> {
+    if let Some(path) = std::env::var_os(CONFIG_VAR) {
+        let path = PathBuf::from(path);
+        eyre::ensure!(
+            path.is_file(),
+            "Config file {} (from ${CONFIG_VAR}) not found",
+            path.display()
+        );
+
+        return Ok(Some(path));
+    }
+
+    Ok(find_in(&search_dirs()))
+}
+
+/// Directories where the config file is looked for, in order.
+fn search_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::from(".")];
+
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+    if let Some(config_home) = config_home {
+        dirs.push(config_home.join("incipit"));
+    }
+
+    dirs.push(PathBuf::from("/etc/incipit"));
+
+    dirs
+}
+
+/// Returns the first `incipit.<ext>` file in `dirs`.
+fn find_in(dirs: &[PathBuf]) -> Option<PathBuf> {
+    dirs.iter()
+        .flat_map(|dir| EXTENSIONS.map(|ext| dir.join("incipit").with_extension(ext)))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_first_config_in_order() -> eyre::Result<()> {
+        let first = tempfile::tempdir()?;
+        let second = tempfile::tempdir()?;
+        let dirs = [first.path().to_path_buf(), second.path().to_path_buf()];
+
+        assert_eq!(find_in(&dirs), None);
+
+        std::fs::write(second.path().join("incipit.json"), "{}")?;
+        assert_eq!(find_in(&dirs), Some(second.path().join("incipit.json")));
+
+        std::fs::write(second.path().join("incipit.yaml"), "")?;
+        assert_eq!(find_in(&dirs), Some(second.path().join("incipit.yaml")));
+
+        std::fs::write(first.path().join("incipit.yml"), "")?;
+        assert_eq!(find_in(&dirs), Some(first.path().join("incipit.yml")));
+
+        Ok(())
+    }
+}
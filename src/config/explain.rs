@@ -0,0 +1,135 @@
+//! Tracking down where each value of the config comes from (config files, included files,
+//! environment variables or templates).
+
+use color_eyre::eyre;
+use figment::{
+    value::{Dict, Value},
+    Figment, Metadata,
+};
+
+use super::{ENV_PREFIX, ENV_SEPARATOR};
+
+/// A value of the config, and where it comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explained {
+    /// Path of the value, such as `service.git.port`.
+    pub key: String,
+
+    /// The value, formatted as JSON.
+    pub value: String,
+
+    /// Human readable description of where the value comes from.
+    pub source: String,
+}
+
+pub(super) fn explain(figment: &Figment) -> eyre::Result<Vec<Explained>> {
+    let values: Dict = figment.extract()?;
+
+    let mut explained = Vec::new();
+    collect(figment, &values, &mut Vec::new(), &mut explained)?;
+
+    Ok(explained)
+}
+
+fn collect(
+    figment: &Figment,
+    dict: &Dict,
+    path: &mut Vec<String>,
+    explained: &mut Vec<Explained>,
+) -> eyre::Result<()> {
+    for (key, value) in dict {
+        path.push(key.clone());
+
+        if let Value::Dict(_, dict) = value {
+            collect(figment, dict, path, explained)?;
+        } else {
+            let key = path.join(".");
+            let source = figment
+                .find_value(&key)
+                .ok()
+                .and_then(|value| figment.get_metadata(value.tag()))
+                .map_or_else(|| "unknown".to_string(), |meta| describe(meta, path));
+
+            explained.push(Explained {
+                value: serde_json::to_string(value)?,
+                key,
+                source,
+            });
+        }
+
+        path.pop();
+    }
+
+    Ok(())
+}
+
+fn describe(meta: &Metadata, path: &[String]) -> String {
+    if meta.name.contains("environment variable") {
+        let var = path
+            .iter()
+            .map(|key| key.to_ascii_uppercase())
+            .collect::<Vec<_>>()
+            .join(ENV_SEPARATOR);
+
+        return format!("environment variable {ENV_PREFIX}{var}");
+    }
+
+    match &meta.source {
+        Some(source) => format!("{} ({source})", meta.name),
+        None => meta.name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{providers::Serialized, Profile, Provider};
+
+    use crate::config::{file_figment, Config};
+
+    use super::*;
+
+    /// Stands in for the `INCIPIT_` environment variables, which other tests would see if they
+    /// were set for real.
+    struct FakeEnv(Serialized<u16>);
+
+    impl Provider for FakeEnv {
+        fn metadata(&self) -> Metadata {
+            Metadata::named("`INCIPIT_` environment variable(s)")
+        }
+
+        fn data(&self) -> figment::Result<figment::value::Map<Profile, Dict>> {
+            self.0.data()
+        }
+    }
+
+    #[test]
+    fn explains_files_and_nested_env() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.toml");
+        std::fs::write(
+            &path,
+            "[service.explained]\nport = 1\nhost = \"explained.example.com\"\n",
+        )?;
+
+        let env = || FakeEnv(Serialized::default("service.explained.port", 8264));
+        let config = Config::figment_with_env(file_figment(&path), env())
+            .and_then(|f| Config::from_figment(&f));
+        let explained =
+            Config::figment_with_env(file_figment(&path), env()).and_then(|f| explain(&f));
+
+        assert_eq!(config?.services[0].port, Some(8264));
+
+        let explained = explained?;
+        let find = |key: &str| explained.iter().find(|e| e.key == key).unwrap().clone();
+
+        assert_eq!(
+            find("service.explained.port").source,
+            "environment variable INCIPIT_SERVICE__EXPLAINED__PORT"
+        );
+        assert!(find("service.explained.host")
+            .source
+            .contains(&path.display().to_string()));
+
+        Ok(())
+    }
+}
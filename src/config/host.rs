@@ -0,0 +1,216 @@
+//! Host patterns, to route several subdomains to the same service.
+//!
+//! - `git.example.com` only matches `git.example.com`.
+//! - `*.example.com` matches exactly one more label, like `alice.example.com`.
+//! - `**.example.com` matches one or more labels, like `alice.example.com` or
+//!   `docs.alice.example.com`.
+//!
+//! When several patterns match a host, an exact host wins over a wildcard, a longer suffix wins
+//! over a shorter one, and `*` wins over `**` with the same suffix (see
+//! [`crate::drawbridge::RoutingTable`]).
+//!
+//! Hosts are compared in their normalized form (see [`normalize`]), both in the config and in
+//! requests.
+
+use std::{fmt, num::ParseIntError, ops::Deref};
+
+use color_eyre::eyre;
+
+/// The hosts of a service. The first one is the primary host, and the rest are aliases.
+///
+/// In the config it can be a single host or a list of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(try_from = "OneOrMany", into = "OneOrMany")]
+pub struct Hosts(Vec<String>);
+
+impl Hosts {
+    /// The primary host, which aliases redirect to.
+    pub fn primary(&self) -> &str {
+        self.0.first().map_or("", String::as_str)
+    }
+
+    /// The hosts other than the primary one.
+    pub fn aliases(&self) -> &[String] {
+        self.0.get(1..).unwrap_or_default()
+    }
+}
+
+impl Deref for Hosts {
+    type Target = [String];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<&str> for Hosts {
+    fn from(host: &str) -> Self {
+        Hosts(vec![host.to_string()])
+    }
+}
+
+impl From<String> for Hosts {
+    fn from(host: String) -> Self {
+        Hosts(vec![host])
+    }
+}
+
+impl From<Vec<String>> for Hosts {
+    fn from(hosts: Vec<String>) -> Self {
+        Hosts(hosts)
+    }
+}
+
+impl fmt::Display for Hosts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join(", "))
+    }
+}
+
+/// A host or a list of hosts, as written in the config.
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+#[serde(untagged)]
+#[schemars(rename = "Hosts")]
+pub(super) enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl TryFrom<OneOrMany> for Hosts {
+    type Error = String;
+
+    fn try_from(hosts: OneOrMany) -> Result<Self, Self::Error> {
+        match hosts {
+            OneOrMany::One(host) => Ok(Hosts(vec![host])),
+            OneOrMany::Many(hosts) if hosts.is_empty() => {
+                Err("a service needs at least one host".to_string())
+            }
+            OneOrMany::Many(hosts) => Ok(Hosts(hosts)),
+        }
+    }
+}
+
+impl From<Hosts> for OneOrMany {
+    fn from(mut hosts: Hosts) -> Self {
+        match hosts.0.len() {
+            1 => OneOrMany::One(hosts.0.remove(0)),
+            _ => OneOrMany::Many(hosts.0),
+        }
+    }
+}
+
+/// Normalizes a host or host pattern: lowercases it, removes the trailing dot of fully qualified
+/// names and converts internationalized names to punycode (`bücher.example` becomes
+/// `xn--bcher-kva.example`).
+pub fn normalize(host: &str) -> eyre::Result<String> {
+    let trimmed = host.strip_suffix('.').unwrap_or(host);
+
+    // IPv6 addresses, like `[::1]`.
+    if trimmed.starts_with('[') {
+        return Ok(trimmed.to_ascii_lowercase());
+    }
+
+    let (wildcard, name) = match trimmed.find("*.") {
+        Some(i) => trimmed.split_at(i + 2),
+        None => ("", trimmed),
+    };
+
+    let name = idna::domain_to_ascii(name).map_err(|_| eyre::eyre!("Invalid host `{host}`"))?;
+
+    Ok(format!("{wildcard}{name}"))
+}
+
+/// Normalizes the host of a request, as found in the `Host` header or the URI (such as
+/// `Git.Example.com.:443`).
+///
+/// The port is removed if it is a default one (80 or 443) or `listener_port` (the one incipit
+/// listens on), since those don't tell services apart. Other ports are kept, so the host doesn't
+/// match any service.
+pub fn normalize_authority(authority: &str, listener_port: u16) -> Option<String> {
+    let (host, port) = split_port(authority).ok()?;
+    let host = normalize(host).ok()?;
+
+    match port {
+        Some(port) if ![80, 443, listener_port].contains(&port) => Some(format!("{host}:{port}")),
+        _ => Some(host),
+    }
+}
+
+/// Splits `host:port` into the host and the port, if there is one. IPv6 addresses have to be in
+/// brackets, like `[::1]:8080`.
+pub fn split_port(authority: &str) -> Result<(&str, Option<u16>), ParseIntError> {
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !port.ends_with(']') => Ok((host, Some(port.parse()?))),
+        _ => Ok((authority, None)),
+    }
+}
+
+/// Checks that `pattern` is a valid host or host pattern: wildcards can only be the whole first
+/// label.
+pub fn validate(pattern: &str) -> eyre::Result<()> {
+    let suffix = pattern
+        .strip_prefix("**.")
+        .or_else(|| pattern.strip_prefix("*."))
+        .unwrap_or(pattern);
+
+    eyre::ensure!(!suffix.is_empty(), "Host `{pattern}` is empty");
+    eyre::ensure!(
+        !suffix.contains('*'),
+        "Invalid host `{pattern}`: wildcards (`*` or `**`) can only be the first label, as in \
+         `*.example.com`"
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_wildcards() {
+        assert!(validate("**.example.com").is_ok());
+        assert!(validate("git.*.example.com").is_err());
+        assert!(validate("*").is_err());
+    }
+
+    #[test]
+    fn normalizes_hosts() {
+        let normalize = |host| normalize(host).unwrap();
+        assert_eq!(normalize("Git.Example.com."), "git.example.com");
+        assert_eq!(normalize("bücher.example"), "xn--bcher-kva.example");
+        assert_eq!(normalize("*.Bücher.example"), "*.xn--bcher-kva.example");
+        assert_eq!(normalize("**.example.com"), "**.example.com");
+
+        let authority = |authority| normalize_authority(authority, 3456);
+        assert_eq!(
+            authority("GIT.example.com:80").as_deref(),
+            Some("git.example.com")
+        );
+        assert_eq!(
+            authority("git.example.com.:3456").as_deref(),
+            Some("git.example.com")
+        );
+        assert_eq!(
+            authority("git.example.com:8080").as_deref(),
+            Some("git.example.com:8080")
+        );
+        assert_eq!(authority("[::1]:443").as_deref(), Some("[::1]"));
+        assert_eq!(authority("git.example.com:http"), None);
+    }
+
+    #[test]
+    fn one_or_many_hosts() {
+        let hosts: Hosts = serde_json::from_str(r#"["git.example.com", "git.lan"]"#).unwrap();
+        assert_eq!(hosts.primary(), "git.example.com");
+        assert_eq!(hosts.aliases(), ["git.lan"]);
+
+        let host: Hosts = serde_json::from_str(r#""git.example.com""#).unwrap();
+        assert_eq!(
+            serde_json::to_string(&host).unwrap(),
+            r#""git.example.com""#
+        );
+
+        assert!(serde_json::from_str::<Hosts>("[]").is_err());
+    }
+}
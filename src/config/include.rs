@@ -0,0 +1,159 @@
+//! Splitting the config across several files with `include`.
+//!
+//! Included files have the same layout as the main config file, but they can only have
+//! `[service.*]` tables. This makes it possible to have, for example, a `services.d/` directory
+//! with one file per service.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{self, Context as _};
+use figment::{value::Value, Figment};
+
+/// Merges the files matched by the `include` patterns into `figment`.
+///
+/// Fails if an included file has anything other than services, so that it can't change the
+/// global settings, and if a service is defined in more than one file, since it is most likely a
+/// mistake and silently merging the definitions would be confusing.
+pub(super) fn merge(
+    mut figment: Figment,
+    base_dir: &Path,
+    patterns: &[String],
+) -> eyre::Result<Figment> {
+    let main_source = super::main_file(&figment);
+
+    let mut defined_in: HashMap<String, Option<PathBuf>> = service_names(&figment)?
+        .into_iter()
+        .map(|name| (name, main_source.clone()))
+        .collect();
+
+    for path in files(base_dir, patterns)? {
+        let included = super::file_figment(&path);
+
+        let keys: BTreeMap<String, Value> = included
+            .extract()
+            .wrap_err_with(|| format!("Failed to read included file {}", path.display()))?;
+        if let Some(key) = keys.keys().find(|key| *key != "service") {
+            eyre::bail!(
+                "Included file {} can only define services, not `{key}`",
+                path.display()
+            );
+        }
+
+        for name in service_names(&included)
+            .wrap_err_with(|| format!("Failed to read included file {}", path.display()))?
+        {
+            if let Some(other) = defined_in.insert(name.clone(), Some(path.clone())) {
+                eyre::bail!(
+                    "Service `{name}` is defined both in {} and in {}",
+                    other
+                        .as_deref()
+                        .map_or("the environment".into(), |p| p.display().to_string()),
+                    path.display(),
+                );
+            }
+        }
+
+        figment = figment.merge(included);
+    }
+
+    Ok(figment)
+}
+
+/// Lists the files matched by `patterns`, resolved relative to `base_dir`.
+///
+/// Files matched by the same pattern are sorted, so that the order doesn't depend on the
+/// filesystem. Patterns without wildcards must match an existing file.
+pub(super) fn files(base_dir: &Path, patterns: &[String]) -> eyre::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for pattern in patterns {
+        let full_pattern = absolute_pattern(base_dir, pattern);
+
+        if !is_glob(pattern) {
+            let path = PathBuf::from(full_pattern);
+            eyre::ensure!(path.is_file(), "Included file {} not found", path.display());
+            files.push(path);
+            continue;
+        }
+
+        let mut matched = glob::glob(&full_pattern)
+            .wrap_err_with(|| format!("Invalid include pattern `{pattern}`"))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        matched.sort();
+        files.extend(matched.into_iter().filter(|path| path.is_file()));
+    }
+
+    Ok(files)
+}
+
+/// Returns `pattern` as an absolute glob pattern, relative to `base_dir`.
+pub(super) fn absolute_pattern(base_dir: &Path, pattern: &str) -> String {
+    if Path::new(pattern).is_absolute() {
+        pattern.to_string()
+    } else {
+        let base_dir = glob::Pattern::escape(&base_dir.to_string_lossy());
+        format!("{base_dir}/{pattern}")
+    }
+}
+
+/// Returns the directory that has to be watched to notice changes in the files matched by
+/// `pattern`, and whether it has to be watched recursively.
+pub(super) fn pattern_root(base_dir: &Path, pattern: &str) -> (PathBuf, bool) {
+    let path = base_dir.join(pattern);
+    let components: Vec<_> = path.components().collect();
+
+    let first_glob = components
+        .iter()
+        .position(|component| is_glob(&component.as_os_str().to_string_lossy()));
+
+    match first_glob {
+        Some(i) => {
+            let root: PathBuf = components[..i].iter().collect();
+            (root, i + 1 < components.len())
+        }
+        None => {
+            let root = path.parent().map(Path::to_path_buf).unwrap_or(path);
+            (root, false)
+        }
+    }
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+fn service_names(figment: &Figment) -> eyre::Result<Vec<String>> {
+    if !figment.contains("service") {
+        return Ok(Vec::new());
+    }
+
+    let services: BTreeMap<String, Value> = figment.extract_inner("service")?;
+    Ok(services.into_keys().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_roots() {
+        let base = Path::new("/etc/incipit");
+
+        assert_eq!(
+            pattern_root(base, "services.d/*.toml"),
+            (PathBuf::from("/etc/incipit/services.d"), false)
+        );
+        assert_eq!(
+            pattern_root(base, "services.d/*/service.toml"),
+            (PathBuf::from("/etc/incipit/services.d"), true)
+        );
+        assert_eq!(
+            pattern_root(base, "extra.toml"),
+            (PathBuf::from("/etc/incipit"), false)
+        );
+    }
+}
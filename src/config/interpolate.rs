@@ -0,0 +1,303 @@
+//! References inside config values, resolved when the config is loaded.
+//!
+//! Strings (hosts, commands, environment variables, upstreams and repo urls) can contain
+//! references of the form `${...}`:
+//!
+//! - `${env:VAR}` is replaced by the environment variable `VAR`.
+//! - `${file:/run/secrets/db}` is replaced by the contents of the file (without the trailing
+//!   newline), which is handy for secrets. Relative paths are relative to the config file.
+//! - `${service.db.port}` is replaced by the field of another service. Supported fields are
+//!   `name`, `port`, `host` (the primary one), `command.run`, `command.build` and `env.VAR`.
+//!
+//! `$${` can be used to write a literal `${`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use color_eyre::eyre::{self, Context as _};
+
+use super::{CommandConfig, Config};
+
+/// Resolves all the references in `config`.
+pub(super) fn interpolate(config: &mut Config) -> eyre::Result<()> {
+    let mut resolver = Resolver::new(config);
+
+    if let Some(host) = &config.incipit_host {
+        config.incipit_host = Some(resolver.expand(host).wrap_err("In `incipit_host`")?);
+    }
+
+    for service in &mut config.services {
+        let prefix = format!("service.{}", service.name);
+        let context = || format!("In service `{}` ({})", service.name, service.origin());
+
+        let mut host = vec![resolver
+            .resolve(&format!("{prefix}.host"))
+            .wrap_err_with(context)?];
+        for alias in service.host.aliases() {
+            host.push(resolver.expand(alias).wrap_err_with(context)?);
+        }
+
+        let command = match &service.command {
+            Some(command) => Some(CommandConfig {
+                run: resolver
+                    .resolve(&format!("{prefix}.command.run"))
+                    .wrap_err_with(context)?,
+                build: match command.build {
+                    Some(_) => Some(
+                        resolver
+                            .resolve(&format!("{prefix}.command.build"))
+                            .wrap_err_with(context)?,
+                    ),
+                    None => None,
+                },
+            }),
+            None => None,
+        };
+
+        let env = service
+            .env
+            .keys()
+            .map(|var| {
+                let value = resolver.resolve(&format!("{prefix}.env.{var}"))?;
+                Ok((var.clone(), value))
+            })
+            .collect::<eyre::Result<BTreeMap<_, _>>>()
+            .wrap_err_with(context)?;
+
+        let upstream = match &service.upstream {
+            Some(upstream) => Some(resolver.expand(upstream).wrap_err_with(context)?),
+            None => None,
+        };
+
+        let url = match &service.repo {
+            Some(repo) => Some(resolver.expand(&repo.url).wrap_err_with(context)?),
+            None => None,
+        };
+
+        service.host = host.into();
+        service.env = env;
+        service.command = command;
+        service.upstream = upstream;
+        if let (Some(repo), Some(url)) = (&mut service.repo, url) {
+            repo.url = url;
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves references, keeping track of the ones in progress to detect cycles.
+struct Resolver {
+    /// Raw (unresolved) values that can be referenced, by key.
+    raw: HashMap<String, String>,
+    /// Directory that the paths of `${file:}` are relative to.
+    root: PathBuf,
+    resolved: HashMap<String, String>,
+    in_progress: Vec<String>,
+}
+
+impl Resolver {
+    fn new(config: &Config) -> Self {
+        let mut raw = HashMap::new();
+
+        for service in &config.services {
+            let prefix = format!("service.{}", service.name);
+
+            raw.insert(format!("{prefix}.name"), service.name.clone());
+            if let Some(port) = service.port {
+                raw.insert(format!("{prefix}.port"), port.to_string());
+            }
+            raw.insert(format!("{prefix}.host"), service.host.primary().to_string());
+
+            if let Some(command) = &service.command {
+                raw.insert(format!("{prefix}.command.run"), command.run.clone());
+
+                if let Some(build) = &command.build {
+                    raw.insert(format!("{prefix}.command.build"), build.clone());
+                }
+            }
+
+            for (var, value) in &service.env {
+                raw.insert(format!("{prefix}.env.{var}"), value.clone());
+            }
+        }
+
+        Self {
+            raw,
+            root: config.root_dir(),
+            resolved: HashMap::new(),
+            in_progress: Vec::new(),
+        }
+    }
+
+    /// Returns the fully resolved value of `key`.
+    fn resolve(&mut self, key: &str) -> eyre::Result<String> {
+        if let Some(value) = self.resolved.get(key) {
+            return Ok(value.clone());
+        }
+
+        if let Some(start) = self.in_progress.iter().position(|k| k == key) {
+            let cycle = self.in_progress[start..].join("` -> `");
+            eyre::bail!("Reference cycle: `{cycle}` -> `{key}`");
+        }
+
+        let Some(raw) = self.raw.get(key).cloned() else {
+            eyre::bail!("`${{{key}}}` doesn't refer to any value");
+        };
+
+        self.in_progress.push(key.to_string());
+        let value = self.expand(&raw);
+        self.in_progress.pop();
+
+        let value = value?;
+        self.resolved.insert(key.to_string(), value.clone());
+
+        Ok(value)
+    }
+
+    /// Replaces all the references in `text`.
+    fn expand(&mut self, text: &str) -> eyre::Result<String> {
+        let mut output = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find('$') {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            if let Some(after) = rest.strip_prefix("$${") {
+                output.push_str("${");
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("${") {
+                let Some(end) = after.find('}') else {
+                    eyre::bail!("Unclosed `${{` in `{text}`");
+                };
+
+                output.push_str(&self.lookup(&after[..end])?);
+                rest = &after[end + 1..];
+            } else {
+                output.push('$');
+                rest = &rest[1..];
+            }
+        }
+
+        output.push_str(rest);
+
+        Ok(output)
+    }
+
+    fn lookup(&mut self, reference: &str) -> eyre::Result<String> {
+        if let Some(var) = reference.strip_prefix("env:") {
+            std::env::var(var)
+                .wrap_err_with(|| format!("Environment variable `{var}` in `${{{reference}}}`"))
+        } else if let Some(path) = reference.strip_prefix("file:") {
+            let content = std::fs::read_to_string(self.root.join(path))
+                .wrap_err_with(|| format!("Can't read `{path}` in `${{{reference}}}`"))?;
+
+            Ok(content.strip_suffix('\n').unwrap_or(&content).to_string())
+        } else if reference.starts_with("service.") {
+            self.resolve(reference)
+        } else {
+            eyre::bail!(
+                "Unknown reference `${{{reference}}}`, expected `env:`, `file:` or `service.`"
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServiceConfig;
+
+    fn service(name: &str, port: u16, host: &str, run: &str) -> ServiceConfig {
+        ServiceConfig {
+            name: name.into(),
+            port: Some(port),
+            host: host.into(),
+            command: Some(CommandConfig {
+                run: run.into(),
+                build: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn references_other_services() -> eyre::Result<()> {
+        let mut config = Config {
+            services: vec![
+                service("db", 5432, "db.lan", "postgres -p ${service.db.port}"),
+                service(
+                    "app",
+                    3000,
+                    "app.${service.db.host}",
+                    "app --db ${service.db.host}:${service.db.port} --cost $$5 $${literal}",
+                ),
+            ],
+            ..Default::default()
+        };
+
+        interpolate(&mut config)?;
+
+        assert_eq!(
+            config.services[0].command.as_ref().unwrap().run,
+            "postgres -p 5432"
+        );
+        assert_eq!(config.services[1].host.primary(), "app.db.lan");
+        assert_eq!(
+            config.services[1].command.as_ref().unwrap().run,
+            "app --db db.lan:5432 --cost $$5 ${literal}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_files_and_env() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let secret = dir.path().join("secret");
+        std::fs::write(&secret, "hunter2\n")?;
+
+        let mut app = service("app", 3000, "app.lan", "app");
+        app.env
+            .insert("PASSWORD".into(), format!("${{file:{}}}", secret.display()));
+        app.env.insert("TOKEN".into(), "${file:secret}".into());
+        app.env
+            .insert("HOME".into(), "${env:INCIPIT_TEST_UNSET_VAR}".into());
+
+        let mut config = Config {
+            services: vec![app.clone()],
+            ..Default::default()
+        };
+        let error = format!("{:#}", interpolate(&mut config).unwrap_err());
+        assert!(error.contains("INCIPIT_TEST_UNSET_VAR"), "{error}");
+
+        app.env.remove("HOME");
+        let mut config = Config {
+            file_path: Some(dir.path().join("incipit.toml")),
+            services: vec![app],
+            ..Default::default()
+        };
+        interpolate(&mut config)?;
+        assert_eq!(config.services[0].env["PASSWORD"], "hunter2");
+        assert_eq!(config.services[0].env["TOKEN"], "hunter2");
+
+        Ok(())
+    }
+
+    #[test]
+    fn detects_cycles() {
+        let mut config = Config {
+            services: vec![
+                service("a", 1, "${service.b.host}", "a"),
+                service("b", 2, "${service.a.host}", "b"),
+            ],
+            ..Default::default()
+        };
+
+        let error = format!("{:#}", interpolate(&mut config).unwrap_err());
+        assert!(error.contains("cycle"), "{error}");
+    }
+}
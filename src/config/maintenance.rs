@@ -0,0 +1,142 @@
+//! Maintenance mode, in `[service.<name>.maintenance]`: while it is on, the hosts of the service
+//! get a 503 (with its `503` error page) instead of reaching it, except for some addresses and
+//! for clients with a bypass cookie.
+//!
+//! It can also be turned on and off from the dashboard, which overrides `enabled` until incipit
+//! restarts.
+
+use std::{net::IpAddr, str::FromStr};
+
+use color_eyre::eyre;
+
+/// Maintenance mode of a service.
+#[derive(
+    Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize, schemars::JsonSchema,
+)]
+pub struct MaintenanceConfig {
+    /// Whether the service is in maintenance.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// When clients should try again, in seconds, sent in the `Retry-After` header.
+    #[serde(default = "default_retry_after")]
+    pub retry_after: u64,
+
+    /// Addresses that still reach the service, as IPs or ranges (such as `192.168.1.0/24`).
+    ///
+    /// This is the address that connects to incipit, so behind another proxy it is the address
+    /// of that proxy.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+
+    /// Clients with an `incipit-maintenance` cookie with this value still reach the service.
+    pub bypass_token: Option<String>,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retry_after: default_retry_after(),
+            allow: Vec::new(),
+            bypass_token: None,
+        }
+    }
+}
+
+fn default_retry_after() -> u64 {
+    300
+}
+
+impl MaintenanceConfig {
+    /// The ranges of `allow`.
+    pub fn allowed(&self) -> eyre::Result<Vec<IpRange>> {
+        self.allow.iter().map(|range| range.parse()).collect()
+    }
+}
+
+/// An IP address, or a range of them in CIDR notation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack listener show up as IPv4-mapped IPv6 addresses.
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+
+        match (self.addr, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len));
+                let mask = mask.unwrap_or(0);
+                u32::from(range) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len));
+                let mask = mask.unwrap_or(0);
+                u128::from(range) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = eyre::Report;
+
+    fn from_str(range: &str) -> eyre::Result<Self> {
+        let invalid = || eyre::eyre!("Invalid address `{range}`, expected an IP or a range");
+
+        let (addr, prefix_len) = match range.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (range, None),
+        };
+
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .parse()
+                .ok()
+                .filter(|&len| len <= max_len)
+                .ok_or_else(invalid)?,
+            None => max_len,
+        };
+
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ranges() -> eyre::Result<()> {
+        let lan: IpRange = "192.168.1.0/24".parse()?;
+        assert!(lan.contains([192, 168, 1, 42].into()));
+        assert!(!lan.contains([192, 168, 2, 1].into()));
+        assert!(lan.contains("::ffff:192.168.1.7".parse()?));
+
+        let host: IpRange = "10.0.0.5".parse()?;
+        assert!(host.contains([10, 0, 0, 5].into()));
+        assert!(!host.contains([10, 0, 0, 6].into()));
+
+        let everything: IpRange = "0.0.0.0/0".parse()?;
+        assert!(everything.contains([8, 8, 8, 8].into()));
+
+        let v6: IpRange = "fd00::/8".parse()?;
+        assert!(v6.contains("fd12::1".parse()?));
+        assert!(!v6.contains([10, 0, 0, 5].into()));
+
+        assert!("192.168.1.0/33".parse::<IpRange>().is_err());
+        assert!("example.com".parse::<IpRange>().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1110 @@
+mod discover;
+mod explain;
+pub(crate) mod host;
+mod include;
+mod interpolate;
+mod maintenance;
+mod redirect;
+mod schema;
+mod template;
+mod variant;
+mod watch;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{self, Context as _};
+use figment::Figment;
+
+pub use explain::Explained;
+pub use host::Hosts;
+pub use maintenance::{IpRange, MaintenanceConfig};
+pub use redirect::{PathPattern, RedirectConfig};
+pub use schema::schema;
+pub use variant::VariantConfig;
+pub use watch::{watch, ConfigWatcher, ReloadStatus};
+
+use template::ServiceTemplate;
+
+/// Prefix of the environment variables that configure incipit.
+const ENV_PREFIX: &str = "INCIPIT_";
+
+/// Separator of nested keys in environment variables, such as `INCIPIT_SERVICE__GIT__PORT` for
+/// `service.git.port`.
+const ENV_SEPARATOR: &str = "__";
+
+/// Returns the main config file, which is the first one that was added to `figment`.
+fn main_file(figment: &Figment) -> Option<PathBuf> {
+    figment
+        .metadata()
+        .find_map(|meta| meta.source.as_ref()?.file_path().map(Path::to_path_buf))
+}
+
+/// Reads a single config file, guessing the format from the extension (TOML by default).
+fn file_figment(path: &Path) -> Figment {
+    use figment::providers::{Format as _, Json, Toml, Yaml};
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => Figment::from(Json::file_exact(path)),
+        Some("yaml" | "yml") => Figment::from(Yaml::file_exact(path)),
+        _ => Figment::from(Toml::file_exact(path)),
+    }
+}
+
+/// Global configuration of incipit. See [`service::Config`] for configuring services.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(try_from = "FileConfig")]
+pub struct Config {
+    /// Path to the root directory for other relative paths. If `None`, it will default to the
+    /// directory where `uoh.toml` is located.
+    ///
+    /// It is where the git repos are cloned to, and the root from which relative paths are
+    /// evaluated (such as `../path/to/file`).
+    ///
+    /// If not set explicitly in the config file, it will be the directory where the config is
+    /// located.
+    pub file_path: Option<PathBuf>,
+
+    /// The services that incipit runs. See [`service::Config`].
+    pub services: Vec<ServiceConfig>,
+
+    /// Host on which to access the incipit dashboard. If not set, incipit's dashboard won't be
+    /// accessible, but it will still start the services and reverse-proxy requests.
+    pub incipit_host: Option<String>,
+
+    /// Address to run incipit on.
+    ///
+    /// Defaults to `0.0.0.0` (to expose to network)
+    pub addr: Option<IpAddr>,
+
+    /// Port to run incipit on.
+    ///
+    /// Default to 80 for HTTP, consider setting it to 443 if you're using HTTPS.
+    pub port: Option<u16>,
+
+    /// Path where the database is stored.
+    ///
+    /// Defaults to `$root_path/incipit.db`
+    pub db_path: Option<PathBuf>,
+
+    /// Other config files to include, as paths or glob patterns relative to the config file
+    /// (for example, `services.d/*.toml`).
+    ///
+    /// The services defined in them are added to [`Config::services`]. A service can only be
+    /// defined in one file.
+    pub include: Vec<String>,
+
+    /// Rules to redirect requests, checked before the services.
+    pub redirects: Vec<RedirectConfig>,
+
+    /// Redirect every request that didn't come through HTTPS to HTTPS (with a 308).
+    ///
+    /// incipit doesn't terminate TLS itself, so this relies on the proxy in front of it setting
+    /// `X-Forwarded-Proto`.
+    pub redirect_to_https: bool,
+
+    /// Pages that incipit answers with when it can't get a response for a request, by status
+    /// (see [`ErrorPageConfig`]). Services can override them.
+    pub error_pages: BTreeMap<String, ErrorPageConfig>,
+
+    /// How long to wait for a service to start answering a request, in seconds, before giving
+    /// up with a 504.
+    ///
+    /// Defaults to 60 seconds.
+    pub upstream_timeout: Option<u64>,
+
+    /// How many idle connections to each service are kept open, to be reused by the next
+    /// requests. With 0, every request gets a new connection.
+    ///
+    /// Defaults to 32.
+    pub upstream_max_idle: Option<usize>,
+
+    /// How long idle connections to services are kept open, in seconds.
+    ///
+    /// Defaults to 90 seconds.
+    pub upstream_idle_timeout: Option<u64>,
+
+    /// Name of the service that gets the requests for hosts that no service (or redirect) has,
+    /// such as a landing page, or a service that is reached by the IP address of the machine.
+    pub default_service: Option<String>,
+
+    /// Addresses that can change things from the dashboard (such as putting services in
+    /// maintenance), as IPs or ranges.
+    ///
+    /// If it isn't set, nobody can. Behind a reverse proxy or a tunnel on the same machine, every
+    /// request comes from the loopback address, so allowing it allows everyone.
+    pub dashboard_allow: Vec<String>,
+}
+
+impl Config {
+    /// Loads the config from the first config file found in the standard locations (see
+    /// [`Config::discover`]), overridden by `INCIPIT_` environment variables.
+    ///
+    /// If there is no config file, the config comes from the environment alone.
+    pub fn new() -> eyre::Result<Self> {
+        match Self::discover()? {
+            Some(path) => {
+                tracing::info!("Using config file {}", path.display());
+                Self::load(&path)
+            }
+            None => {
+                tracing::warn!("No config file found, using environment variables only");
+                Self::from_figment(&Self::figment(Figment::new())?)
+            }
+        }
+    }
+
+    /// Finds the config file. The first of these that exists is used:
+    ///
+    /// 1. The file in `$INCIPIT_CONFIG` (which must exist if set).
+    /// 2. `./incipit.{toml,yaml,yml,json}`.
+    /// 3. `$XDG_CONFIG_HOME/incipit/incipit.{toml,yaml,yml,json}` (`XDG_CONFIG_HOME` defaults to
+    ///    `~/.config`).
+    /// 4. `/etc/incipit/incipit.{toml,yaml,yml,json}`.
+    pub fn discover() -> eyre::Result<Option<PathBuf>> {
+        discover::discover()
+    }
+
+    /// Loads the config from the file at `path` (TOML, YAML or JSON, depending on the
+    /// extension), overridden by `INCIPIT_` environment variables.
+    ///
+    /// Unlike [`Config::new`], this doesn't depend on the current directory, so it is what should
+    /// be used to reload a config that is already known.
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        // The watcher reports absolute paths, so we need ours to be absolute too.
+        let path = &std::path::absolute(path)
+            .wrap_err_with(|| format!("Invalid config path {}", path.display()))?;
+
+        let mut config = Self::figment(file_figment(path))
+            .and_then(|figment| Self::from_figment(&figment))
+            .wrap_err_with(|| format!("Failed to load config from {}", path.display()))?;
+
+        config.file_path = config.file_path.or_else(|| Some(path.to_path_buf()));
+
+        Ok(config)
+    }
+
+    /// Lists every value of the config that [`Config::new`] would load, along with where it
+    /// comes from.
+    ///
+    /// Values are listed as written, before resolving references such as `${env:VAR}`.
+    pub fn explain() -> eyre::Result<Vec<Explained>> {
+        let files = match Self::discover()? {
+            Some(path) => file_figment(&std::path::absolute(path)?),
+            None => Figment::new(),
+        };
+
+        explain::explain(&Self::figment(files)?)
+    }
+
+    /// Gathers all the sources of the config on top of the given config files: the files they
+    /// `include`, the `INCIPIT_` environment variables and the templates.
+    fn figment(files: Figment) -> eyre::Result<Figment> {
+        use figment::providers::Env;
+
+        // `INCIPIT_CONFIG` is the path to the config file, not part of the config.
+        let env = Env::prefixed(ENV_PREFIX)
+            .ignore(&["config"])
+            .split(ENV_SEPARATOR);
+
+        Self::figment_with_env(files, env)
+    }
+
+    /// Like [`Config::figment`], with `env` instead of the environment variables.
+    fn figment_with_env(files: Figment, env: impl figment::Provider) -> eyre::Result<Figment> {
+        let include: Vec<String> = if files.contains("include") {
+            files.extract_inner("include")?
+        } else {
+            Vec::new()
+        };
+
+        let base_dir = match main_file(&files).as_deref().and_then(Path::parent) {
+            Some(dir) => dir.to_path_buf(),
+            None => std::env::current_dir()?,
+        };
+
+        let figment = include::merge(files, &base_dir, &include)?.merge(env);
+
+        template::apply(figment)
+    }
+
+    fn from_figment(figment: &Figment) -> eyre::Result<Self> {
+        let mut config: Config = figment.extract()?;
+        config.file_path = config.file_path.or_else(|| main_file(figment));
+
+        for service in &mut config.services {
+            service.source = figment
+                .find_metadata(&format!("service.{}", service.name))
+                .and_then(|meta| meta.source.as_ref())
+                .and_then(|source| source.file_path())
+                .map(Path::to_path_buf);
+        }
+
+        interpolate::interpolate(&mut config)?;
+        config.normalize()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Normalizes the hosts, so that they can be compared with the (normalized) hosts of
+    /// requests (see [`host::normalize`]), and the paths, so that they look like `/grafana`.
+    fn normalize(&mut self) -> eyre::Result<()> {
+        if let Some(incipit_host) = &self.incipit_host {
+            self.incipit_host = Some(host::normalize(incipit_host).wrap_err("In `incipit_host`")?);
+        }
+
+        for service in &mut self.services {
+            let hosts = service
+                .host
+                .iter()
+                .map(|h| host::normalize(h))
+                .collect::<eyre::Result<Vec<_>>>()
+                .wrap_err_with(|| {
+                    format!("In service `{}` ({})", service.name, service.origin())
+                })?;
+
+            service.host = hosts.into();
+
+            if let Some(path) = &service.path {
+                let path = path.trim_matches('/');
+                service.path = (!path.is_empty()).then(|| format!("/{path}"));
+            }
+        }
+
+        for redirect in &mut self.redirects {
+            let hosts = redirect
+                .host
+                .iter()
+                .map(|h| host::normalize(h))
+                .collect::<eyre::Result<Vec<_>>>()
+                .wrap_err("In `redirects`")?;
+
+            redirect.host = hosts.into();
+        }
+
+        Ok(())
+    }
+
+    /// Checks the invariants that can't be expressed in the types of the config.
+    fn validate(&self) -> eyre::Result<()> {
+        for status in self.error_pages.keys() {
+            ErrorPageConfig::status(status)?;
+        }
+
+        self.dashboard_allowed().wrap_err("In `dashboard_allow`")?;
+
+        if let Some(default) = &self.default_service {
+            eyre::ensure!(
+                self.services.iter().any(|s| s.name == *default),
+                "The default service `{default}` doesn't exist"
+            );
+        }
+
+        for redirect in &self.redirects {
+            redirect
+                .validate()
+                .wrap_err_with(|| format!("In the redirect to `{}`", redirect.to))?;
+        }
+
+        for (i, service) in self.services.iter().enumerate() {
+            let context = || format!("In service `{}` ({})", service.name, service.origin());
+
+            for (j, host) in service.host.iter().enumerate() {
+                host::validate(host).wrap_err_with(context)?;
+
+                eyre::ensure!(
+                    !service.host[..j].contains(host),
+                    "Service `{}` ({}) has the host `{host}` more than once",
+                    service.name,
+                    service.origin(),
+                );
+
+                if let Some(other) = self.services[..i]
+                    .iter()
+                    .find(|s| s.host.contains(host) && s.path == service.path)
+                {
+                    eyre::bail!(
+                        "Services `{}` ({}) and `{}` ({}) have the same host `{host}`{}",
+                        other.name,
+                        other.origin(),
+                        service.name,
+                        service.origin(),
+                        match &service.path {
+                            Some(path) => format!(" and path `{path}`"),
+                            None => String::new(),
+                        },
+                    );
+                }
+            }
+
+            let targets = [
+                service.port.is_some(),
+                service.upstream.is_some(),
+                service.socket.is_some(),
+                service.static_files.is_some(),
+            ];
+            match targets.iter().filter(|&&set| set).count() {
+                0 => eyre::bail!(
+                    "Service `{}` ({}) needs a `port` (or an `upstream`, a `socket` or `static`)",
+                    service.name,
+                    service.origin(),
+                ),
+                1 => {}
+                _ => eyre::bail!(
+                    "Service `{}` ({}) can only have one of `port`, `upstream`, `socket` and `static`",
+                    service.name,
+                    service.origin(),
+                ),
+            }
+
+            variant::validate(service).wrap_err_with(context)?;
+
+            for status in service.error_pages.keys() {
+                ErrorPageConfig::status(status).wrap_err_with(context)?;
+            }
+
+            if let Some(maintenance) = &service.maintenance {
+                maintenance.allowed().wrap_err_with(context)?;
+            }
+
+            eyre::ensure!(
+                service.fastcgi.is_none() || service.static_files.is_none(),
+                "Service `{}` ({}) can't use `fastcgi` with `static`, it needs the address of \
+                 the FastCGI server in `port`, `upstream` or `socket`",
+                service.name,
+                service.origin(),
+            );
+
+            if let Some(upstream) = &service.upstream {
+                eyre::ensure!(
+                    matches!(host::split_port(upstream), Ok((host, Some(_))) if !host.is_empty()),
+                    "Invalid upstream `{upstream}` in service `{}` ({}), expected `host:port`",
+                    service.name,
+                    service.origin(),
+                );
+            }
+
+            eyre::ensure!(
+                !service.redirect_aliases || !service.host.primary().contains('*'),
+                "Service `{}` ({}) redirects its aliases, so its first host can't be a wildcard",
+                service.name,
+                service.origin(),
+            );
+
+            for dependency in &service.depends_on {
+                eyre::ensure!(
+                    self.services.iter().any(|s| s.name == *dependency),
+                    "Service `{}` ({}) depends on unknown service `{dependency}`",
+                    service.name,
+                    service.origin(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Directory that the files of `service` are in: the checkout of its repo if it has one, or
+    /// [`Config::root_dir`] otherwise.
+    pub fn service_dir(&self, service: &ServiceConfig) -> PathBuf {
+        match service.repo {
+            Some(_) => self.root_dir().join(&service.name),
+            None => self.root_dir(),
+        }
+    }
+
+    /// The ranges of `dashboard_allow`. If it isn't set, nobody can change things from the
+    /// dashboard.
+    pub fn dashboard_allowed(&self) -> eyre::Result<Vec<IpRange>> {
+        self.dashboard_allow
+            .iter()
+            .map(|range| range.parse())
+            .collect()
+    }
+
+    /// Directory of the config file, which relative paths are relative to. Defaults to the
+    /// current directory if the config doesn't come from a file.
+    pub fn root_dir(&self) -> PathBuf {
+        self.file_path
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."))
+    }
+
+    pub fn from_file(path: &Path) -> eyre::Result<Self> {
+        let content = std::fs::read_to_string(path).wrap_err("Failed to read config")?;
+        let config: Config = toml::from_str(&content).wrap_err("Failed to parse config")?;
+        Ok(config)
+    }
+}
+
+/// Layout of the config that gets deserialized from. This is a separate struct to make
+/// the file more convinient to write and the actual condig value more sensible at the time of
+/// using it.
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+#[schemars(
+    title = "incipit config",
+    description = "Configuration of incipit, usually in `incipit.toml`."
+)]
+struct FileConfig {
+    /// The services that incipit runs, by name.
+    #[serde(default)]
+    service: HashMap<String, ServiceConfig<Option<()>>>,
+
+    /// Host on which to access the incipit dashboard.
+    incipit_host: Option<String>,
+
+    /// Address to run incipit on. Defaults to `0.0.0.0`.
+    addr: Option<IpAddr>,
+
+    /// Port to run incipit on. Defaults to 80.
+    port: Option<u16>,
+
+    /// Path where the database is stored.
+    db_path: Option<PathBuf>,
+
+    /// Other config files to include, as paths or glob patterns relative to this file.
+    #[serde(default)]
+    include: Vec<String>,
+
+    /// Fields that every service inherits.
+    // The services have already inherited these in `template::apply`, so they are only here for
+    // the schema and to check their types.
+    #[allow(dead_code)]
+    defaults: Option<ServiceTemplate>,
+
+    /// Named templates that services can inherit from with `extends`.
+    #[serde(default)]
+    #[allow(dead_code)]
+    templates: BTreeMap<String, ServiceTemplate>,
+
+    /// Rules to redirect requests (checked in order, before the services).
+    #[serde(default)]
+    redirects: Vec<RedirectConfig>,
+
+    /// Redirect every request that didn't come through HTTPS (according to `X-Forwarded-Proto`)
+    /// to HTTPS.
+    #[serde(default)]
+    redirect_to_https: bool,
+
+    /// Pages that incipit answers with when it can't get a response for a request, by status
+    /// (`404` for unknown hosts and missing files, `502`, `503` and `504` for services that fail).
+    #[serde(default)]
+    error_pages: BTreeMap<String, ErrorPageConfig>,
+
+    /// How long to wait for a service to start answering a request, in seconds. Defaults to 60.
+    upstream_timeout: Option<u64>,
+
+    /// How many idle connections to each service are kept open. Defaults to 32.
+    upstream_max_idle: Option<usize>,
+
+    /// How long idle connections to services are kept open, in seconds. Defaults to 90.
+    upstream_idle_timeout: Option<u64>,
+
+    /// Name of the service that gets the requests for unknown hosts (instead of a 404).
+    default_service: Option<String>,
+
+    /// Addresses that can change things from the dashboard, as IPs or ranges. If it isn't set,
+    /// nobody can.
+    #[serde(default)]
+    dashboard_allow: Vec<String>,
+}
+
+impl TryFrom<FileConfig> for Config {
+    type Error = eyre::Error;
+    fn try_from(file: FileConfig) -> eyre::Result<Self> {
+        let config = Self {
+            file_path: None,
+            services: file
+                .service
+                .into_iter()
+                .map(|(name, service)| ServiceConfig {
+                    name,
+                    port: service.port,
+                    upstream: service.upstream,
+                    socket: service.socket,
+                    static_files: service.static_files,
+                    fastcgi: service.fastcgi,
+                    variants: service.variants,
+                    sticky: service.sticky,
+                    error_pages: service.error_pages,
+                    maintenance: service.maintenance,
+                    host: service.host,
+                    redirect_aliases: service.redirect_aliases,
+                    path: service.path,
+                    strip_path: service.strip_path,
+                    repo: service.repo,
+                    command: service.command,
+                    env: service.env,
+                    extends: service.extends,
+                    restart: service.restart,
+                    health_check: service.health_check,
+                    working_dir: service.working_dir,
+                    depends_on: service.depends_on,
+                    source: service.source,
+                })
+                .collect(),
+            incipit_host: file.incipit_host,
+            addr: file.addr,
+            port: file.port,
+            db_path: file.db_path,
+            include: file.include,
+            redirects: file.redirects,
+            redirect_to_https: file.redirect_to_https,
+            error_pages: file.error_pages,
+            upstream_timeout: file.upstream_timeout,
+            upstream_max_idle: file.upstream_max_idle,
+            upstream_idle_timeout: file.upstream_idle_timeout,
+            default_service: file.default_service,
+            dashboard_allow: file.dashboard_allow,
+        };
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+#[schemars(rename = "ServiceConfig")]
+pub struct ServiceConfig<T = String> {
+    /// Name of the service.
+    #[serde(skip_serializing)]
+    #[schemars(skip)]
+    pub name: T,
+
+    /// Port that the service listens on, on the same machine as incipit. Either this, `upstream`,
+    /// `socket` or `static` is needed.
+    pub port: Option<u16>,
+
+    /// Address of a service that runs elsewhere (and isn't managed by incipit), such as
+    /// `192.168.1.20:8123` or `nas.lan:5000`. Host names are resolved with DNS when connecting.
+    pub upstream: Option<String>,
+
+    /// Unix domain socket that the service listens on, instead of a port, such as
+    /// `/run/app.sock`. Relative paths are relative to the config file.
+    pub socket: Option<PathBuf>,
+
+    /// Directory of files that incipit serves itself, for static sites that don't need a process
+    /// of their own.
+    #[serde(rename = "static")]
+    pub static_files: Option<StaticConfig>,
+
+    /// Speak FastCGI to the service (at its `port`, `upstream` or `socket`) instead of HTTP, for
+    /// PHP applications running in php-fpm.
+    pub fastcgi: Option<FastCgiConfig>,
+
+    /// Other instances of the service that get some of its requests, chosen by header, cookie,
+    /// query parameter or weight, such as a rewrite that is being tested against the current
+    /// version.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<VariantConfig>,
+
+    /// Keep sending clients to the variant (or the service itself) that they were first sent to
+    /// by weight, with a cookie.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sticky: bool,
+
+    /// Pages that incipit answers with when it can't get a response from the service, which
+    /// override the global `error_pages`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub error_pages: BTreeMap<String, ErrorPageConfig>,
+
+    /// Answer requests with a maintenance page instead of forwarding them to the service, while
+    /// data is migrated for example.
+    pub maintenance: Option<MaintenanceConfig>,
+
+    /// Host of the service. If `None`, it will default to <name>.<domain> (where the domain is
+    /// obtained from the global config).
+    ///
+    /// Can be a wildcard like `*.example.com` (one label) or `**.example.com` (any number of
+    /// labels), in which case the matched subdomain is sent to the service in the
+    /// `X-Incipit-Subdomain` header.
+    ///
+    /// Can also be a list of hosts, where the first one is the primary host and the rest are
+    /// aliases.
+    #[schemars(with = "host::OneOrMany")]
+    pub host: Hosts,
+
+    /// Redirect (with a 301) requests to the aliases of the service to its primary host, so that
+    /// there is only one canonical URL.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub redirect_aliases: bool,
+
+    /// Path prefix that the service is served under, such as `/grafana`, so that several
+    /// services can share a host. Requests go to the service with the longest matching prefix.
+    pub path: Option<String>,
+
+    /// Remove `path` from requests before forwarding them, for services that expect to be served
+    /// at the root.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strip_path: bool,
+
+    /// Options related to the Git repository.
+    pub repo: Option<RepoConfig>,
+
+    /// Options related to commands for updating and running the service
+    pub command: Option<CommandConfig>,
+
+    /// Environment variables to set when running the service.
+    #[serde(default, deserialize_with = "deserialize_env")]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    #[schemars(with = "BTreeMap<String, EnvValue>")]
+    pub env: BTreeMap<String, String>,
+
+    /// Name of the template (in `[templates.<name>]`) that this service inherits its fields from.
+    pub extends: Option<String>,
+
+    /// What to do when the service exits.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub restart: RestartPolicy,
+
+    /// How to check whether the service is healthy.
+    pub health_check: Option<HealthCheckConfig>,
+
+    /// Directory to run the service in. Relative paths are relative to the config file.
+    ///
+    /// Defaults to the directory of the config file.
+    pub working_dir: Option<PathBuf>,
+
+    /// Names of the services that need to be running for this one to work.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+
+    /// File where the service is defined, if any. Used for error messages.
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+impl ServiceConfig {
+    /// Describes where the service was defined, for error messages.
+    pub fn origin(&self) -> String {
+        match &self.source {
+            Some(path) => format!("defined in {}", path.display()),
+            None => "defined in the environment".to_string(),
+        }
+    }
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// Value of an environment variable in the config. Numbers and booleans are accepted too, so
+/// that `env = { PORT = 6942 }` works.
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+#[serde(untagged)]
+enum EnvValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+fn deserialize_env<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let env: BTreeMap<String, EnvValue> = serde::Deserialize::deserialize(deserializer)?;
+
+    Ok(env
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                EnvValue::String(value) => value,
+                EnvValue::Integer(value) => value.to_string(),
+                EnvValue::Float(value) => value.to_string(),
+                EnvValue::Bool(value) => value.to_string(),
+            };
+
+            (key, value)
+        })
+        .collect())
+}
+
+#[derive(
+    Debug, Clone, serde::Deserialize, serde::Serialize, schemars::JsonSchema, clap::Parser,
+)]
+pub struct RepoConfig {
+    /// url to the git repository.
+    ///
+    /// It needs to be accessible by the user running `incipit`. That is,
+    /// either public or with the appropriate permissions.
+    pub url: String,
+
+    /// Branch to pull from. If `None`, it will default to `main`.
+    pub branch: Option<String>,
+    // TODO:
+    // pub auto_pull: bool,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct CommandConfig {
+    /// Command to run the service
+    pub run: String,
+
+    /// Command to build the service after pulling the repository.
+    pub build: Option<String>,
+}
+
+/// A directory that incipit serves as a static site.
+#[derive(
+    Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize, schemars::JsonSchema,
+)]
+pub struct StaticConfig {
+    /// Directory to serve. If the service has a `repo`, relative paths are relative to its
+    /// checkout (a directory named after the service, next to the config file), so that its build
+    /// output (such as `dist`) can be served. Otherwise, they are relative to the config file.
+    pub dir: PathBuf,
+
+    /// Files that are served for a directory, in order of preference.
+    #[serde(default = "default_index")]
+    pub index: Vec<String>,
+
+    /// Serve the index of the root directory for paths that don't exist, for single page apps
+    /// that do their routing in the browser.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub spa: bool,
+
+    /// List the files of directories that don't have an index.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub list_directories: bool,
+}
+
+fn default_index() -> Vec<String> {
+    vec!["index.html".to_string()]
+}
+
+/// How requests are turned into FastCGI requests, for PHP applications.
+///
+/// Paths with a `.php` file in them run that script, with the rest of the path as `PATH_INFO`
+/// (`/index.php/Main_Page` runs `/index.php`). Paths ending in `/` run the `index` script of the
+/// directory. Any other path is served from `root` as a static file if it exists. Scripts only run
+/// if they exist, and the `front_controller` runs instead of the ones that don't.
+#[derive(
+    Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize, schemars::JsonSchema,
+)]
+pub struct FastCgiConfig {
+    /// Document root of the application, where scripts and files are looked up. If the service
+    /// has a `repo`, relative paths are relative to its checkout. Otherwise, they are relative to
+    /// the config file.
+    pub root: PathBuf,
+
+    /// Script that is run for directories.
+    #[serde(default = "default_fastcgi_index")]
+    pub index: String,
+
+    /// Script that is run for paths that aren't a script or an existing file, for applications
+    /// that route every request through one script (such as `index.php`). If not set, those paths
+    /// get a 404.
+    pub front_controller: Option<String>,
+
+    /// Extra parameters to send to the FastCGI server, or to override the ones that incipit
+    /// sends (such as `PHP_VALUE`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, String>,
+
+    /// Largest request body that is sent to the application, in bytes, since incipit reads the
+    /// whole body first. Requests with a larger one get a 413.
+    ///
+    /// Defaults to 8 MiB.
+    #[serde(default = "default_fastcgi_max_body_size")]
+    pub max_body_size: u64,
+
+    /// Largest response that the application can send, in bytes, since incipit reads the whole
+    /// response first. Larger ones get a 502.
+    ///
+    /// Defaults to 64 MiB.
+    #[serde(default = "default_fastcgi_max_response_size")]
+    pub max_response_size: u64,
+}
+
+fn default_fastcgi_index() -> String {
+    "index.php".to_string()
+}
+
+fn default_fastcgi_max_body_size() -> u64 {
+    8 * 1024 * 1024
+}
+
+fn default_fastcgi_max_response_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+/// The page for an error status, as templates for each format (relative to the config file). The
+/// format is chosen by the `Accept` header of the request, and formats without a template get
+/// incipit's own page.
+///
+/// Templates can contain `{{status}}`, `{{reason}}` (such as `Bad Gateway`), `{{message}}`,
+/// `{{host}}`, `{{path}}` and `{{request_id}}`, which are escaped for the format.
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize, schemars::JsonSchema,
+)]
+pub struct ErrorPageConfig {
+    /// Template of the HTML page, for browsers.
+    pub html: Option<PathBuf>,
+
+    /// Template of the JSON document, for API clients.
+    pub json: Option<PathBuf>,
+}
+
+impl ErrorPageConfig {
+    /// Parses the status that a page is for, which is a key of `error_pages`.
+    pub fn status(key: &str) -> eyre::Result<u16> {
+        match key.parse() {
+            Ok(status @ 400..=599) => Ok(status),
+            _ => eyre::bail!("Invalid status `{key}` in `error_pages`, expected one like `404`"),
+        }
+    }
+}
+
+/// What to do when a service exits.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Deserialize,
+    serde::Serialize,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Leave the service stopped.
+    Never,
+
+    /// Restart the service only if it exited with an error.
+    #[default]
+    OnFailure,
+
+    /// Always restart the service.
+    Always,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct HealthCheckConfig {
+    /// Path that gets requested (with `GET`) to check the service. Any successful status means
+    /// that the service is healthy.
+    pub path: String,
+
+    /// Seconds between checks. Defaults to 30.
+    pub interval: Option<u64>,
+}
+
+impl Config {
+    pub fn addr(&self) -> IpAddr {
+        const DEFAULT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0));
+        self.addr.unwrap_or(DEFAULT)
+    }
+
+    pub fn socket(&self) -> SocketAddr {
+        SocketAddr::new(self.addr(), self.port.unwrap_or(80))
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum GetConfigError {
+    #[error("Config not found")]
+    ConfigNotFound,
+
+    #[error("Failed to read config")]
+    ReadConfigError(#[from] std::io::Error),
+
+    #[error("Failed to parse config")]
+    ConfigParseError(#[from] toml::de::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore]
+    fn test_find_config() {
+        let path = Config::new();
+        assert!(path.is_ok());
+    }
+
+    #[test]
+    fn test_load_invalid_config() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.toml");
+        std::fs::write(
+            &path,
+            "[service.git]\nport = 99999\nhost = \"git.example.com\"\n",
+        )?;
+
+        let err = format!("{:#}", Config::load(&path).unwrap_err());
+        assert!(err.contains("`99999`, expected u16"), "{err}");
+        assert!(err.contains("service.git.port"), "{err}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_included_services() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.toml");
+        std::fs::write(&path, "include = [\"services.d/*.toml\"]\n")?;
+        std::fs::create_dir(dir.path().join("services.d"))?;
+        std::fs::write(
+            dir.path().join("services.d/git.toml"),
+            "[service.git]\nport = 8264\nhost = \"git.example.com\"\n",
+        )?;
+        std::fs::write(
+            dir.path().join("services.d/wiki.toml"),
+            "[service.wiki]\nport = 8080\nhost = \"wiki.example.com\"\n",
+        )?;
+
+        let config = Config::load(&path)?;
+
+        let git = config.services.iter().find(|s| s.name == "git").unwrap();
+        assert_eq!(git.port, Some(8264));
+        assert_eq!(git.source, Some(dir.path().join("services.d/git.toml")));
+        assert_eq!(config.services.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_included_settings() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.toml");
+        std::fs::write(&path, "port = 8080\ninclude = [\"git.toml\"]\n")?;
+        std::fs::write(
+            dir.path().join("git.toml"),
+            "port = 80\n[service.git]\nport = 8264\nhost = \"git.example.com\"\n",
+        )?;
+
+        let err = format!("{:#}", Config::load(&path).unwrap_err());
+        assert!(
+            err.contains("can only define services, not `port`"),
+            "{err}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_duplicated_service() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.toml");
+        std::fs::write(
+            &path,
+            "include = [\"git.toml\"]\n[service.git]\nport = 8264\nhost = \"git.example.com\"\n",
+        )?;
+        std::fs::write(
+            dir.path().join("git.toml"),
+            "[service.git]\nport = 8265\nhost = \"git.example.com\"\n",
+        )?;
+
+        let error = format!("{:#}", Config::load(&path).unwrap_err());
+        assert!(error.contains("git.toml"), "{error}");
+        assert!(error.contains("incipit.toml"), "{error}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_env_and_references() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [service.app]
+            port = 6942
+            host = "app.example.com"
+            command.run = "node build"
+            env = { PORT = "${service.app.port}", DEBUG = true }
+            "#,
+        )?;
+
+        let config = Config::load(&path)?;
+
+        assert_eq!(config.services[0].env["PORT"], "6942");
+        assert_eq!(config.services[0].env["DEBUG"], "true");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_upstreams() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.toml");
+        let load = |service: &str| {
+            std::fs::write(
+                &path,
+                format!("[service.nas]\nhost = \"nas.lan\"\n{service}"),
+            )?;
+            Config::load(&path)
+        };
+
+        let config = load("upstream = \"192.168.1.20:5000\"")?;
+        assert_eq!(
+            config.services[0].upstream.as_deref(),
+            Some("192.168.1.20:5000")
+        );
+
+        assert!(load("upstream = \"nas.local\"").is_err());
+        assert!(load("upstream = \"nas.local:5000\"\nport = 5000").is_err());
+        assert!(load("").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_yaml() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.yaml");
+        std::fs::write(
+            &path,
+            "port: 8080\nservice:\n  git:\n    port: 8264\n    host: git.example.com\n",
+        )?;
+
+        let config = Config::load(&path)?;
+
+        assert_eq!(config.port, Some(8080));
+        assert_eq!(config.services[0].host.primary(), "git.example.com");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_from_file_config() -> eyre::Result<()> {
+        let file_config = FileConfig {
+            service: HashMap::new(),
+            incipit_host: Some("incipit.example.com".into()),
+            addr: Some([127, 0, 0, 1].into()),
+            port: Some(8080),
+            db_path: Some(PathBuf::from("db")),
+            include: Vec::new(),
+            defaults: None,
+            templates: BTreeMap::new(),
+            redirects: Vec::new(),
+            redirect_to_https: false,
+            error_pages: BTreeMap::new(),
+            upstream_timeout: None,
+            upstream_max_idle: None,
+            upstream_idle_timeout: None,
+            default_service: None,
+            dashboard_allow: Vec::new(),
+        };
+
+        let config = Config::try_from(file_config)?;
+
+        assert_eq!(config.file_path, None);
+        assert_eq!(config.incipit_host, Some("incipit.example.com".into()));
+        assert_eq!(config.addr, Some([127, 0, 0, 1].into()));
+        assert_eq!(config.port, Some(8080));
+        assert_eq!(config.db_path, Some(PathBuf::from("db")));
+        assert!(config.dashboard_allowed()?.is_empty());
+
+        Ok(())
+    }
+}
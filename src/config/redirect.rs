@@ -0,0 +1,170 @@
+//! Redirect rules, in `[[redirects]]`.
+//!
+//! Redirects are checked in order, before services, so they can also redirect hosts that a
+//! service uses (for example, only some of its paths).
+
+use std::fmt;
+
+use color_eyre::eyre;
+use regex::Regex;
+
+use super::{host, Hosts};
+
+/// Status codes that make sense for a redirect.
+const STATUSES: [u16; 5] = [301, 302, 303, 307, 308];
+
+/// A rule to redirect requests instead of forwarding them to a service.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct RedirectConfig {
+    /// Hosts to redirect, with the same syntax as the hosts of services (so wildcards work too).
+    #[schemars(with = "host::OneOrMany")]
+    pub host: Hosts,
+
+    /// Regular expression that the path has to match for the redirect to apply, such as
+    /// `^/blog/(\d+)$`. Its captures can be used in `to` as `$1` or `${name}`.
+    ///
+    /// If not set, every path is redirected, and the path is appended to `to`.
+    pub path: Option<PathPattern>,
+
+    /// Where to redirect to: a URL (`https://www.example.com`), a host (`www.example.com`,
+    /// keeping the scheme of the request) or a path on the same host (`/posts/$1`).
+    ///
+    /// The query of the request is kept, unless `to` has its own.
+    pub to: String,
+
+    /// Status code of the redirect: 301 (the default) or 308 for permanent redirects, and 302,
+    /// 303 or 307 for temporary ones.
+    #[serde(default = "default_status")]
+    pub status: u16,
+}
+
+fn default_status() -> u16 {
+    301
+}
+
+impl RedirectConfig {
+    /// Returns where a request for `path` is redirected to (without the query), or `None` if
+    /// the path doesn't match.
+    pub fn location(&self, path: &str) -> Option<String> {
+        match &self.path {
+            Some(pattern) => {
+                let captures = pattern.0.captures(path)?;
+
+                let mut location = String::new();
+                captures.expand(&self.to, &mut location);
+
+                Some(location)
+            }
+            None => Some(format!("{}{path}", self.to.trim_end_matches('/'))),
+        }
+    }
+
+    pub(super) fn validate(&self) -> eyre::Result<()> {
+        for host in self.host.iter() {
+            host::validate(host)?;
+        }
+
+        eyre::ensure!(
+            STATUSES.contains(&self.status),
+            "Invalid redirect status {}, expected one of {STATUSES:?}",
+            self.status
+        );
+
+        Ok(())
+    }
+}
+
+/// A regular expression for paths, compiled when the config is loaded.
+#[derive(Clone, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+#[serde(try_from = "String", into = "String")]
+#[schemars(transparent)]
+pub struct PathPattern(#[schemars(with = "String")] Regex);
+
+impl TryFrom<String> for PathPattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Regex::new(&pattern).map(PathPattern)
+    }
+}
+
+impl From<PathPattern> for String {
+    fn from(pattern: PathPattern) -> Self {
+        pattern.0.as_str().to_string()
+    }
+}
+
+impl fmt::Debug for PathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redirect(path: Option<&str>, to: &str) -> RedirectConfig {
+        RedirectConfig {
+            host: "example.com".into(),
+            path: path.map(|p| PathPattern::try_from(p.to_string()).unwrap()),
+            to: to.into(),
+            status: default_status(),
+        }
+    }
+
+    #[test]
+    fn locations() {
+        let www = redirect(None, "www.example.com/");
+        assert_eq!(
+            www.location("/about").as_deref(),
+            Some("www.example.com/about")
+        );
+
+        let blog = redirect(
+            Some(r"^/blog/(?<id>\d+)$"),
+            "https://blog.example.com/posts/${id}",
+        );
+        assert_eq!(
+            blog.location("/blog/42").as_deref(),
+            Some("https://blog.example.com/posts/42")
+        );
+        assert_eq!(blog.location("/blog/latest"), None);
+
+        let mut invalid = redirect(None, "/");
+        invalid.status = 200;
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn loads_redirects() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [[redirects]]
+            host = ["Example.com", "*.old-example.com"]
+            to = "https://www.example.com"
+
+            [[redirects]]
+            host = "www.example.com"
+            path = '^/blog/(\d+)$'
+            to = "/posts/$1"
+            status = 308
+            "#,
+        )?;
+
+        let config = crate::Config::load(&path)?;
+        assert_eq!(config.redirects[0].host.primary(), "example.com");
+        assert_eq!(config.redirects[1].status, 308);
+
+        std::fs::write(
+            &path,
+            "[[redirects]]\nhost = \"a.com\"\npath = \"(\"\nto = \"/\"\n",
+        )?;
+        assert!(crate::Config::load(&path).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+//! JSON Schema of the config file, so that editors can autocomplete and validate it.
+
+use schemars::{gen::SchemaSettings, schema::RootSchema};
+
+use super::FileConfig;
+
+/// Generates the JSON Schema of the config file.
+///
+/// It is derived from the types the config gets deserialized into (including their doc comments,
+/// which become descriptions), so it can't get out of sync with what incipit accepts.
+pub fn schema() -> RootSchema {
+    SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<FileConfig>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_describes_config() {
+        let schema = serde_json::to_value(schema()).unwrap();
+
+        let properties = &schema["properties"];
+        for property in ["service", "include", "defaults", "templates", "port"] {
+            assert!(properties.get(property).is_some(), "missing {property}");
+        }
+
+        let service = &schema["definitions"]["ServiceConfig"];
+        assert!(service["properties"]["upstream"]["description"]
+            .as_str()
+            .unwrap()
+            .starts_with("Address of a service that runs elsewhere"));
+        assert!(service["properties"].get("name").is_none());
+        assert!(service["required"]
+            .as_array()
+            .unwrap()
+            .contains(&"host".into()));
+
+        assert_eq!(
+            properties["templates"]["additionalProperties"]["$ref"],
+            "#/definitions/ServiceTemplate"
+        );
+        let template = &schema["definitions"]["ServiceTemplate"];
+        assert!(template.get("required").is_none());
+    }
+}
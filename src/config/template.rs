@@ -0,0 +1,299 @@
+//! Service templates and defaults.
+//!
+//! Services can inherit fields from a named template in `[templates.<name>]` with
+//! `extends = "<name>"`, and templates can extend other templates. Every service also inherits
+//! from the `[defaults]` block.
+//!
+//! Inheritance is field by field (tables such as `env` or `command` are merged recursively), and
+//! the service always wins over its template, which wins over the templates it extends, which win
+//! over the defaults.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre;
+use figment::{
+    providers::Serialized,
+    value::{Dict, Map, Value},
+    Figment, Metadata, Profile, Provider, Source,
+};
+
+use super::{
+    host, CommandConfig, EnvValue, ErrorPageConfig, FastCgiConfig, HealthCheckConfig, Hosts,
+    MaintenanceConfig, RepoConfig, RestartPolicy, StaticConfig, VariantConfig,
+};
+
+/// Fields that services inherit, see `extends`. These are the fields of a service, but all of them
+/// are optional.
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+#[allow(dead_code)]
+pub(super) struct ServiceTemplate {
+    /// Port that the service listens on, on the same machine as incipit.
+    port: Option<u16>,
+
+    /// Address of a service that runs elsewhere, such as `192.168.1.20:8123`.
+    upstream: Option<String>,
+
+    /// Unix domain socket that the service listens on, instead of a port.
+    socket: Option<PathBuf>,
+
+    /// Directory of files that incipit serves itself.
+    #[serde(rename = "static")]
+    static_files: Option<StaticConfig>,
+
+    /// Speak FastCGI to the service instead of HTTP.
+    fastcgi: Option<FastCgiConfig>,
+
+    /// Other instances of the service that get some of its requests.
+    variants: Option<Vec<VariantConfig>>,
+
+    /// Keep sending clients to the variant that they were first sent to by weight.
+    sticky: Option<bool>,
+
+    /// Pages that incipit answers with when it can't get a response from the service.
+    error_pages: Option<BTreeMap<String, ErrorPageConfig>>,
+
+    /// Answer requests with a maintenance page instead of forwarding them to the service.
+    maintenance: Option<MaintenanceConfig>,
+
+    /// Host of the service, or a list of hosts where the first one is the primary host.
+    #[schemars(with = "Option<host::OneOrMany>")]
+    host: Option<Hosts>,
+
+    /// Redirect requests to the aliases of the service to its primary host.
+    redirect_aliases: Option<bool>,
+
+    /// Path prefix that the service is served under, such as `/grafana`.
+    path: Option<String>,
+
+    /// Remove `path` from requests before forwarding them.
+    strip_path: Option<bool>,
+
+    /// Options related to the Git repository.
+    repo: Option<RepoConfig>,
+
+    /// Options related to commands for updating and running the service
+    command: Option<CommandConfig>,
+
+    /// Environment variables to set when running the service.
+    env: Option<BTreeMap<String, EnvValue>>,
+
+    /// Name of the template that this one inherits its fields from.
+    extends: Option<String>,
+
+    /// What to do when the service exits.
+    restart: Option<RestartPolicy>,
+
+    /// How to check whether the service is healthy.
+    health_check: Option<HealthCheckConfig>,
+
+    /// Directory to run the service in.
+    working_dir: Option<PathBuf>,
+
+    /// Names of the services that need to be running for this one to work.
+    depends_on: Option<Vec<String>>,
+}
+
+/// Fills in the fields that services inherit from their templates and the defaults.
+pub(super) fn apply(mut figment: Figment) -> eyre::Result<Figment> {
+    let services: BTreeMap<String, Dict> = extract_or_default(&figment, "service")?;
+    let templates: BTreeMap<String, Dict> = extract_or_default(&figment, "templates")?;
+    let defaults: Option<Dict> = extract_or_default(&figment, "defaults")?;
+
+    let source_of = |key: &str| {
+        figment
+            .find_metadata(key)
+            .and_then(|meta| meta.source.as_ref()?.file_path().map(Path::to_path_buf))
+    };
+
+    let mut inherited = Vec::new();
+
+    for (service, fields) in &services {
+        let mut extends = extends(fields);
+        let mut chain: Vec<&str> = Vec::new();
+
+        while let Some(template) = extends {
+            if chain.contains(&template) {
+                eyre::bail!(
+                    "Service `{service}` extends a cycle of templates: `{}` -> `{template}`",
+                    chain.join("` -> `")
+                );
+            }
+
+            let Some(fields) = templates.get(template) else {
+                eyre::bail!("Service `{service}` extends unknown template `{template}`");
+            };
+
+            chain.push(template);
+            inherited.push(Inherited {
+                service: service.clone(),
+                fields: fields.clone(),
+                name: format!("template `{template}`"),
+                source: source_of(&format!("templates.{template}")),
+            });
+
+            extends = self::extends(fields);
+        }
+
+        if let Some(defaults) = &defaults {
+            inherited.push(Inherited {
+                service: service.clone(),
+                fields: defaults.clone(),
+                name: "defaults".to_string(),
+                source: source_of("defaults"),
+            });
+        }
+    }
+
+    // Joining only fills in the values that aren't set yet, so the order in which they are
+    // joined is the order of precedence.
+    for inherited in inherited {
+        figment = figment.join(inherited);
+    }
+
+    Ok(figment)
+}
+
+fn extends(fields: &Dict) -> Option<&str> {
+    fields.get("extends").and_then(Value::as_str)
+}
+
+fn extract_or_default<T>(figment: &Figment, key: &str) -> eyre::Result<T>
+where
+    T: Default + for<'de> serde::Deserialize<'de>,
+{
+    if figment.contains(key) {
+        Ok(figment.extract_inner(key)?)
+    } else {
+        Ok(T::default())
+    }
+}
+
+/// Provides the fields that a service inherits from a template (or from the defaults).
+struct Inherited {
+    service: String,
+    fields: Dict,
+    /// Name of the template, to know where the values come from.
+    name: String,
+    source: Option<PathBuf>,
+}
+
+impl Provider for Inherited {
+    fn metadata(&self) -> Metadata {
+        match &self.source {
+            Some(path) => Metadata::from(self.name.clone(), Source::File(path.clone())),
+            None => Metadata::named(self.name.clone()),
+        }
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, figment::Error> {
+        let mut fields = self.fields.clone();
+        fields.remove("extends");
+
+        let services = Dict::from([(self.service.clone(), Value::from(fields))]);
+        let data = Dict::from([("service".to_string(), Value::from(services))]);
+
+        // Going through `Serialized` gives the values fresh tags, so that they are attributed to
+        // this provider instead of to the file where the template is defined.
+        Serialized::defaults(data).data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, RestartPolicy, ServiceConfig};
+
+    use super::*;
+
+    fn load(content: &str) -> eyre::Result<Config> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.toml");
+        std::fs::write(&path, content)?;
+
+        Config::load(&path)
+    }
+
+    #[test]
+    fn services_inherit_from_templates_and_defaults() -> eyre::Result<()> {
+        let config = load(
+            r#"
+            [defaults]
+            restart = "always"
+            env = { NODE_ENV = "production", TZ = "UTC" }
+
+            [templates.node-app]
+            command.build = "pnpm build"
+            command.run = "node build"
+            env = { PORT = "${service.app.port}" }
+
+            [templates.slow-node-app]
+            extends = "node-app"
+            restart = "on-failure"
+            health_check.path = "/health"
+
+            [service.app]
+            extends = "slow-node-app"
+            port = 6942
+            host = "app.example.com"
+            env = { TZ = "Europe/Amsterdam" }
+            "#,
+        )?;
+
+        let app = &config.services[0];
+        let command = app.command.as_ref().unwrap();
+
+        assert_eq!(command.run, "node build");
+        assert_eq!(command.build.as_deref(), Some("pnpm build"));
+        assert_eq!(app.restart, RestartPolicy::OnFailure);
+        assert_eq!(app.health_check.as_ref().unwrap().path, "/health");
+        assert_eq!(app.env["PORT"], "6942");
+        assert_eq!(app.env["NODE_ENV"], "production");
+        assert_eq!(app.env["TZ"], "Europe/Amsterdam");
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_and_cyclic_templates() {
+        let unknown = load(
+            r#"
+            [service.app]
+            extends = "nope"
+            port = 6942
+            host = "app.example.com"
+            "#,
+        );
+        assert!(format!("{:#}", unknown.unwrap_err()).contains("unknown template `nope`"));
+
+        let cyclic = load(
+            r#"
+            [templates.a]
+            extends = "b"
+
+            [templates.b]
+            extends = "a"
+
+            [service.app]
+            extends = "a"
+            port = 6942
+            host = "app.example.com"
+            "#,
+        );
+        assert!(format!("{:#}", cyclic.unwrap_err()).contains("cycle"));
+    }
+
+    #[test]
+    fn templates_have_the_fields_of_services() {
+        let properties = |schema: schemars::schema::RootSchema| {
+            let properties = schema.schema.object.unwrap().properties;
+            properties.into_keys().collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            properties(schemars::schema_for!(ServiceTemplate)),
+            properties(schemars::schema_for!(ServiceConfig)),
+        );
+    }
+}
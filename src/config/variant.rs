@@ -0,0 +1,150 @@
+//! Variants of a service, in `[[service.<name>.variants]]`, which get some of its requests (for
+//! A/B tests or canary releases).
+//!
+//! A request goes to the first variant whose header, cookie or query rules it matches. Otherwise,
+//! it goes to a variant at random according to their weights (in percent of the requests), or to
+//! the service itself with the remaining weight.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use axum::http::HeaderName;
+use color_eyre::eyre;
+
+use super::{host, ServiceConfig};
+
+/// Another instance of a service.
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct VariantConfig {
+    /// Name of the variant, which is what the sticky cookie stores.
+    pub name: String,
+
+    /// Port that the variant listens on. Like in services, either this, `upstream` or `socket`
+    /// is needed.
+    pub port: Option<u16>,
+
+    /// Address of the variant if it runs elsewhere, such as `192.168.1.20:8123`.
+    pub upstream: Option<String>,
+
+    /// Unix domain socket that the variant listens on.
+    pub socket: Option<PathBuf>,
+
+    /// Percentage of the requests that don't match any rule that go to this variant.
+    #[serde(default)]
+    pub weight: u8,
+
+    /// Send requests with all of these headers (and values) to this variant, such as
+    /// `{ X-Beta = "1" }`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+
+    /// Send requests with all of these cookies (and values) to this variant.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub cookies: BTreeMap<String, String>,
+
+    /// Send requests with all of these query parameters (and values) to this variant.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub query: BTreeMap<String, String>,
+}
+
+/// Checks the variants of `service`.
+pub(super) fn validate(service: &ServiceConfig) -> eyre::Result<()> {
+    if service.variants.is_empty() {
+        return Ok(());
+    }
+
+    eyre::ensure!(
+        service.static_files.is_none(),
+        "Static sites can't have variants"
+    );
+
+    let weight: u32 = service.variants.iter().map(|v| u32::from(v.weight)).sum();
+    eyre::ensure!(
+        weight <= 100,
+        "The weights of the variants add up to {weight}%, which is more than 100%"
+    );
+
+    for (i, variant) in service.variants.iter().enumerate() {
+        let name = &variant.name;
+
+        eyre::ensure!(
+            !name.is_empty() && *name != service.name,
+            "Variants need a name other than the name of the service"
+        );
+        eyre::ensure!(
+            !service.variants[..i].iter().any(|v| v.name == *name),
+            "There are several variants named `{name}`"
+        );
+
+        let targets = [
+            variant.port.is_some(),
+            variant.upstream.is_some(),
+            variant.socket.is_some(),
+        ];
+        eyre::ensure!(
+            targets.iter().filter(|&&set| set).count() == 1,
+            "Variant `{name}` needs one of `port`, `upstream` and `socket`"
+        );
+
+        if let Some(upstream) = &variant.upstream {
+            eyre::ensure!(
+                matches!(host::split_port(upstream), Ok((host, Some(_))) if !host.is_empty()),
+                "Invalid upstream `{upstream}` in variant `{name}`, expected `host:port`"
+            );
+        }
+
+        for header in variant.headers.keys() {
+            eyre::ensure!(
+                HeaderName::try_from(header.as_str()).is_ok(),
+                "Invalid header `{header}` in variant `{name}`"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Config;
+
+    use super::*;
+
+    #[test]
+    fn loads_variants() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.toml");
+        let load = |variants: &str| {
+            std::fs::write(
+                &path,
+                format!("[service.app]\nhost = \"app.example.com\"\nport = 3000\n{variants}"),
+            )?;
+            Config::load(&path)
+        };
+
+        let config = load(
+            r#"
+            sticky = true
+
+            [[service.app.variants]]
+            name = "rewrite"
+            port = 3001
+            weight = 20
+            headers = { X-Beta = "1" }
+            "#,
+        )?;
+        let variant = &config.services[0].variants[0];
+        assert_eq!((variant.port, variant.weight), (Some(3001), 20));
+        assert_eq!(variant.headers["X-Beta"], "1");
+
+        let variant = |fields: &str| format!("[[service.app.variants]]\n{fields}\n");
+        assert!(load(&variant("name = \"a\"")).is_err());
+        assert!(load(&variant("name = \"app\"\nport = 1")).is_err());
+        assert!(load(&(variant("name = \"a\"\nport = 1\nweight = 60").repeat(2))).is_err());
+        assert!(load(&variant(
+            "name = \"a\"\nport = 1\nheaders = { \"a b\" = \"1\" }"
+        ))
+        .is_err());
+
+        Ok(())
+    }
+}
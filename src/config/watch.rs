@@ -0,0 +1,346 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc, Mutex, PoisonError, RwLock,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use color_eyre::eyre;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::{include, Config};
+
+/// How long the config file has to stay untouched before it gets reloaded.
+///
+/// Editors usually save in several steps (truncate, write, rename, chmod...), so a single save
+/// shows up as a burst of events. Waiting for the burst to settle avoids parsing half-written
+/// files.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Outcome of the last attempt to reload the config from disk.
+#[derive(Debug, Clone, Default)]
+pub enum ReloadStatus {
+    /// The config hasn't changed since incipit started.
+    #[default]
+    Initial,
+
+    /// The config was reloaded successfully.
+    Reloaded { at: SystemTime },
+
+    /// The config on disk is invalid, so incipit keeps running with the last good one.
+    Failed { at: SystemTime, error: String },
+}
+
+/// Handle to a running config watcher. The config stops being watched when this is dropped.
+pub struct ConfigWatcher {
+    _watcher: Arc<Mutex<RecommendedWatcher>>,
+}
+
+/// Watches the config file (and the files it includes) and reloads `config` whenever they
+/// change. `on_reload` is called with each new config before it replaces `config`, to update
+/// whatever depends on it (such as the routing table).
+///
+/// If the new config is invalid, `config` is left untouched and the error is stored in `status`.
+/// The watcher keeps running either way, so fixing the file is enough to get it picked up.
+///
+/// Returns `None` if the config was not loaded from a file (so there is nothing to watch).
+pub fn watch(
+    config: Arc<RwLock<Config>>,
+    status: Arc<RwLock<ReloadStatus>>,
+    on_reload: impl Fn(&Config) + Send + 'static,
+) -> eyre::Result<Option<ConfigWatcher>> {
+    let Some(mut watched) = Watched::new(&config.read().unwrap_or_else(PoisonError::into_inner))
+    else {
+        tracing::warn!("Not watching config");
+        return Ok(None);
+    };
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    let watcher = Arc::new(Mutex::new(RecommendedWatcher::new(
+        sender,
+        Default::default(),
+    )?));
+
+    watched.watch(
+        &mut watcher.lock().unwrap_or_else(PoisonError::into_inner),
+        &[],
+    );
+
+    tracing::info!(config_path = ?watched.config_path, "Watching for changes");
+
+    // The thread only keeps a weak reference, so that dropping the `ConfigWatcher` drops the
+    // watcher, which closes the channel and stops the thread.
+    let weak_watcher = Arc::downgrade(&watcher);
+
+    let _handle = thread::spawn(move || {
+        while wait_for_change(&receiver, &watched) {
+            reload(&config, &status, &watched.config_path, &on_reload);
+
+            let Some(watcher) = weak_watcher.upgrade() else {
+                break;
+            };
+
+            // The set of included files might have changed.
+            if let Some(new_watched) =
+                Watched::new(&config.read().unwrap_or_else(PoisonError::into_inner))
+            {
+                new_watched.watch(
+                    &mut watcher.lock().unwrap_or_else(PoisonError::into_inner),
+                    &watched.dirs,
+                );
+                watched = new_watched;
+            }
+        }
+
+        tracing::debug!("Stopped watching config");
+    });
+
+    Ok(Some(ConfigWatcher { _watcher: watcher }))
+}
+
+/// The files that make up a config, and the directories that need to be watched to notice when
+/// they change.
+struct Watched {
+    config_path: PathBuf,
+    include_patterns: Vec<glob::Pattern>,
+    dirs: Vec<(PathBuf, RecursiveMode)>,
+    /// Directories of included files that don't exist, whose creation needs to be noticed to
+    /// start watching them.
+    missing_dirs: Vec<PathBuf>,
+}
+
+impl Watched {
+    fn new(config: &Config) -> Option<Self> {
+        let config_path = config.file_path.clone()?;
+        let base_dir = config_path
+            .parent()
+            .expect("`config_path` is a file so it will always have a parent.");
+
+        // Watch directories instead of files, since editors often replace files (which would
+        // make us lose track of them). This also catches new files matched by `include`.
+        let mut dirs = vec![(base_dir.to_path_buf(), RecursiveMode::NonRecursive)];
+        let mut missing_dirs = Vec::new();
+        for pattern in &config.include {
+            let (dir, recursive) = include::pattern_root(base_dir, pattern);
+            let mode = if recursive {
+                RecursiveMode::Recursive
+            } else {
+                RecursiveMode::NonRecursive
+            };
+
+            // Directories that don't exist yet can't be watched, so their closest ancestor that
+            // exists is, to notice when they get created.
+            let (dir, mode) = match dir.exists() {
+                true => (dir, mode),
+                false => {
+                    let ancestor = dir
+                        .ancestors()
+                        .find(|ancestor| ancestor.exists())
+                        .unwrap_or(base_dir)
+                        .to_path_buf();
+                    missing_dirs.push(dir);
+                    (ancestor, RecursiveMode::NonRecursive)
+                }
+            };
+
+            if !dirs.contains(&(dir.clone(), mode)) {
+                dirs.push((dir, mode));
+            }
+        }
+
+        let include_patterns = config
+            .include
+            .iter()
+            .filter_map(|pattern| {
+                glob::Pattern::new(&include::absolute_pattern(base_dir, pattern)).ok()
+            })
+            .collect();
+
+        Some(Self {
+            config_path,
+            include_patterns,
+            dirs,
+            missing_dirs,
+        })
+    }
+
+    /// Starts watching the directories that weren't in `previous`, and stops watching the ones
+    /// that are gone.
+    fn watch(&self, watcher: &mut RecommendedWatcher, previous: &[(PathBuf, RecursiveMode)]) {
+        for (dir, _) in previous.iter().filter(|dir| !self.dirs.contains(dir)) {
+            let _ = watcher.unwatch(dir);
+        }
+
+        for (dir, mode) in self.dirs.iter().filter(|dir| !previous.contains(dir)) {
+            if let Err(err) = watcher.watch(dir, *mode) {
+                tracing::warn!("Can't watch {} for config changes: {err}", dir.display());
+            }
+        }
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        path == self.config_path
+            || self
+                .include_patterns
+                .iter()
+                .any(|pattern| pattern.matches_path(path))
+            || self.missing_dirs.iter().any(|dir| dir.starts_with(path))
+    }
+}
+
+/// Blocks until one of the watched files changes and then settles down for [`DEBOUNCE`].
+///
+/// Returns `false` when the watcher has been dropped.
+fn wait_for_change(receiver: &Receiver<notify::Result<Event>>, watched: &Watched) -> bool {
+    loop {
+        match receiver.recv() {
+            Ok(Ok(event)) if event.paths.iter().any(|path| watched.contains(path)) => break,
+            Ok(Ok(_)) => continue,
+            Ok(Err(err)) => tracing::warn!("Error while watching config: {err}"),
+            Err(_) => return false,
+        }
+    }
+
+    loop {
+        match receiver.recv_timeout(DEBOUNCE) {
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) => return true,
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+fn reload(
+    config: &RwLock<Config>,
+    status: &RwLock<ReloadStatus>,
+    path: &Path,
+    on_reload: &impl Fn(&Config),
+) {
+    let at = SystemTime::now();
+
+    match Config::load(path) {
+        Ok(new_config) => {
+            tracing::info!("Reloaded config");
+            tracing::debug!("New config: {new_config:#?}");
+
+            on_reload(&new_config);
+            *config.write().unwrap_or_else(PoisonError::into_inner) = new_config;
+            *status.write().unwrap_or_else(PoisonError::into_inner) = ReloadStatus::Reloaded { at };
+        }
+        Err(err) => {
+            tracing::error!("Failed to reload config, keeping the last good one: {err:?}");
+
+            *status.write().unwrap_or_else(PoisonError::into_inner) = ReloadStatus::Failed {
+                at,
+                error: format!("{err:#}"),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = r#"
+        port = 8080
+
+        [service.git]
+        port = 8264
+        host = "git.example.com"
+    "#;
+
+    fn status_of(status: &RwLock<ReloadStatus>) -> ReloadStatus {
+        status.read().unwrap().clone()
+    }
+
+    #[test]
+    fn keeps_last_good_config_and_keeps_watching() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.toml");
+        std::fs::write(&path, VALID)?;
+
+        let config = Arc::new(RwLock::new(Config::load(&path)?));
+        let status = Arc::new(RwLock::new(ReloadStatus::default()));
+        let _watcher = watch(Arc::clone(&config), Arc::clone(&status), |_| {})?;
+
+        std::fs::write(&path, "port = \"not a port\"")?;
+        thread::sleep(DEBOUNCE * 4);
+
+        assert!(matches!(status_of(&status), ReloadStatus::Failed { .. }));
+        assert_eq!(config.read().unwrap().port, Some(8080));
+
+        std::fs::write(&path, VALID.replace("8080", "8081"))?;
+        thread::sleep(DEBOUNCE * 4);
+
+        assert!(matches!(status_of(&status), ReloadStatus::Reloaded { .. }));
+        assert_eq!(config.read().unwrap().port, Some(8081));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reloads_when_included_files_change() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.toml");
+        std::fs::write(&path, "include = [\"services.d/*.toml\"]\n")?;
+        std::fs::create_dir(dir.path().join("services.d"))?;
+
+        let config = Arc::new(RwLock::new(Config::load(&path)?));
+        let status = Arc::new(RwLock::new(ReloadStatus::default()));
+        let reloaded = Arc::new(Mutex::new(Vec::new()));
+        let _watcher = watch(Arc::clone(&config), Arc::clone(&status), {
+            let reloaded = Arc::clone(&reloaded);
+            move |config| reloaded.lock().unwrap().push(config.services.len())
+        })?;
+
+        assert!(config.read().unwrap().services.is_empty());
+
+        std::fs::write(
+            dir.path().join("services.d/git.toml"),
+            "[service.git]\nport = 8264\nhost = \"git.example.com\"\n",
+        )?;
+        thread::sleep(DEBOUNCE * 4);
+
+        assert!(matches!(status_of(&status), ReloadStatus::Reloaded { .. }));
+        assert_eq!(config.read().unwrap().services.len(), 1);
+        assert_eq!(reloaded.lock().unwrap().last(), Some(&1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn watches_included_dirs_that_are_created_later() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.toml");
+        std::fs::write(&path, "include = [\"services.d/*.toml\"]\n")?;
+
+        let config = Arc::new(RwLock::new(Config::load(&path)?));
+        let status = Arc::new(RwLock::new(ReloadStatus::default()));
+        let _watcher = watch(Arc::clone(&config), Arc::clone(&status), |_| {})?;
+
+        std::fs::create_dir(dir.path().join("services.d"))?;
+        std::fs::write(
+            dir.path().join("services.d/git.toml"),
+            "[service.git]\nport = 8264\nhost = \"git.example.com\"\n",
+        )?;
+        thread::sleep(DEBOUNCE * 4);
+
+        assert_eq!(config.read().unwrap().services.len(), 1);
+
+        // The new directory is watched from then on.
+        std::fs::write(
+            dir.path().join("services.d/wiki.toml"),
+            "[service.wiki]\nport = 8080\nhost = \"wiki.example.com\"\n",
+        )?;
+        thread::sleep(DEBOUNCE * 4);
+
+        assert_eq!(config.read().unwrap().services.len(), 2);
+
+        Ok(())
+    }
+}
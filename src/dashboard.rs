@@ -0,0 +1,236 @@
+//! The dashboard served on `incipit_host`.
+
+use std::{
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{Arc, PoisonError, RwLock},
+    time::SystemTime,
+};
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{Html, IntoResponse as _, Redirect, Response},
+    routing::{get, post},
+    Form, Router,
+};
+
+use crate::{
+    config::{Config, ReloadStatus, ServiceConfig},
+    drawbridge::MaintenanceMode,
+    util::escape,
+};
+
+#[derive(Clone)]
+pub(crate) struct DashboardState {
+    pub config: Arc<RwLock<Config>>,
+    pub reload_status: Arc<RwLock<ReloadStatus>>,
+    pub maintenance: Arc<MaintenanceMode>,
+}
+
+pub(crate) fn router(state: DashboardState) -> Router {
+    Router::new()
+        .route("/", get(index))
+        .route("/services/:name/maintenance", post(set_maintenance))
+        .with_state(state)
+}
+
+/// Whether `service` is in maintenance, from the dashboard or its config.
+fn in_maintenance(state: &DashboardState, service: &ServiceConfig) -> bool {
+    let configured = service.maintenance.as_ref().is_some_and(|m| m.enabled);
+    state.maintenance.is_enabled(&service.name, configured)
+}
+
+#[derive(serde::Deserialize)]
+struct MaintenanceForm {
+    enabled: bool,
+}
+
+/// Whether a request that changes something comes from the dashboard itself, rather than from
+/// a page of another site that a visitor of the dashboard has open.
+///
+/// Requests without `Origin` or `Referer` don't come from a browser, so they can't be forged.
+/// HTTP/2 requests can have their host in the URI (`:authority`) instead of in `Host`.
+fn same_origin(headers: &HeaderMap, uri: &Uri) -> bool {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| uri.authority().map(|authority| authority.as_str()));
+    let Some(host) = host else {
+        return false;
+    };
+    let Some(origin) = headers
+        .get(header::ORIGIN)
+        .or_else(|| headers.get(header::REFERER))
+    else {
+        return true;
+    };
+
+    origin
+        .to_str()
+        .ok()
+        .and_then(|origin| origin.split_once("://"))
+        .map(|(_, rest)| rest.split('/').next().unwrap_or_default())
+        .is_some_and(|authority| authority.eq_ignore_ascii_case(host))
+}
+
+/// Puts a service in maintenance, or takes it out of it.
+async fn set_maintenance(
+    State(state): State<DashboardState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    uri: Uri,
+    Path(name): Path<String>,
+    Form(form): Form<MaintenanceForm>,
+) -> Response {
+    let (allowed, known) = {
+        let config = state.config.read().unwrap_or_else(PoisonError::into_inner);
+        let allowed = config.dashboard_allowed().unwrap_or_default();
+        let known = config.services.iter().any(|service| service.name == name);
+        (allowed, known)
+    };
+    if !allowed.iter().any(|range| range.contains(addr.ip())) || !same_origin(&headers, &uri) {
+        tracing::warn!("Refused to change the maintenance of service `{name}` for {addr}");
+        return (StatusCode::FORBIDDEN, "403 - Forbidden").into_response();
+    }
+    if !known {
+        return (StatusCode::NOT_FOUND, "404 - Unknown service").into_response();
+    }
+
+    tracing::info!(
+        "{} maintenance of service `{name}` from the dashboard",
+        if form.enabled { "Starting" } else { "Ending" }
+    );
+    state.maintenance.set(&name, form.enabled);
+
+    Redirect::to("/").into_response()
+}
+
+async fn index(State(state): State<DashboardState>) -> Html<String> {
+    let config = state
+        .config
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    let reload_status = state
+        .reload_status
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+
+    let mut html = String::from("<!DOCTYPE html><html><head><title>incipit</title></head><body>");
+    html.push_str("<h1>incipit</h1>");
+
+    match reload_status {
+        ReloadStatus::Initial => {}
+        ReloadStatus::Reloaded { at } => {
+            let _ = write!(html, "<p>Config reloaded {} ago.</p>", ago(at));
+        }
+        ReloadStatus::Failed { at, error } => {
+            let _ = write!(
+                html,
+                "<p><strong>Config reload failed {} ago</strong>, still using the last good \
+                 config:</p><pre>{}</pre>",
+                ago(at),
+                escape(&error)
+            );
+        }
+    }
+
+    html.push_str("<h2>Services</h2><ul>");
+    for service in &config.services {
+        let maintenance = in_maintenance(&state, service);
+        let _ = write!(
+            html,
+            "<li>{}{} &mdash; {}{} &rarr; {}{}{}{} <form method=\"post\" \
+             action=\"/services/{}/maintenance\" style=\"display:inline\">\
+             <input type=\"hidden\" name=\"enabled\" value=\"{}\">\
+             <button>{}</button></form></li>",
+            escape(&service.name),
+            match config.default_service.as_ref() == Some(&service.name) {
+                true => " (default)",
+                false => "",
+            },
+            escape(&service.host.to_string()),
+            escape(service.path.as_deref().unwrap_or_default()),
+            match (
+                &service.upstream,
+                &service.socket,
+                &service.static_files,
+                service.port,
+            ) {
+                (Some(upstream), _, _, _) => escape(upstream),
+                (_, Some(socket), _, _) => escape(&socket.display().to_string()),
+                (_, _, Some(files), _) =>
+                    format!("files in {}", escape(&files.dir.display().to_string())),
+                (_, _, _, Some(port)) => format!("port {port}"),
+                _ => "nowhere".to_string(),
+            },
+            match service.fastcgi {
+                Some(_) => " (FastCGI)",
+                None => "",
+            },
+            match service.variants.len() {
+                0 => String::new(),
+                n => format!(" (and {n} variant{})", if n == 1 { "" } else { "s" }),
+            },
+            match maintenance {
+                true => " <strong>(in maintenance)</strong>",
+                false => "",
+            },
+            percent_encoding::utf8_percent_encode(
+                &service.name,
+                percent_encoding::NON_ALPHANUMERIC
+            ),
+            !maintenance,
+            match maintenance {
+                true => "End maintenance",
+                false => "Start maintenance",
+            },
+        );
+    }
+    html.push_str("</ul></body></html>");
+
+    Html(html)
+}
+
+fn ago(at: SystemTime) -> String {
+    let secs = at.elapsed().unwrap_or_default().as_secs();
+    format!("{secs}s")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_origins() {
+        let check = |origin: Option<(header::HeaderName, &str)>| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::HOST, "incipit.example.com".parse().unwrap());
+            if let Some((name, value)) = origin {
+                headers.insert(name, value.parse().unwrap());
+            }
+            same_origin(&headers, &Uri::from_static("/"))
+        };
+
+        assert!(check(None));
+        assert!(check(Some((header::ORIGIN, "https://incipit.example.com"))));
+        assert!(check(Some((
+            header::REFERER,
+            "http://incipit.example.com/?a"
+        ))));
+        assert!(!check(Some((header::ORIGIN, "https://evil.example.com"))));
+        assert!(!check(Some((header::ORIGIN, "null"))));
+
+        // HTTP/2 requests, with the host in the URI.
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ORIGIN,
+            "https://incipit.example.com".parse().unwrap(),
+        );
+        let uri = Uri::from_static("https://incipit.example.com/services/app/maintenance");
+        assert!(same_origin(&headers, &uri));
+        assert!(!same_origin(&headers, &Uri::from_static("/")));
+    }
+}
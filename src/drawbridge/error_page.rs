@@ -0,0 +1,282 @@
+//! The pages that incipit answers with when it can't get a response for a request: a host that
+//! it doesn't know, or a service that fails (see [`crate::config::ErrorPageConfig`]).
+//!
+//! The details of the failure are only logged (with the request ID that the page shows), so
+//! that visitors don't see internal errors.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse as _, Response},
+};
+
+use crate::{
+    config::{Config, ErrorPageConfig, ServiceConfig},
+    util::escape,
+};
+
+/// Header with the ID of a request, which is taken from the request if it has one.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const HTML: &str = "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+    <title>{{status}} {{reason}}</title></head><body><h1>{{status}} {{reason}}</h1>\
+    <p>{{message}}</p><p><small>Request ID: {{request_id}}</small></p></body></html>\n";
+
+const JSON: &str = "{\"status\":{{status}},\"error\":\"{{message}}\",\
+    \"request_id\":\"{{request_id}}\"}\n";
+
+const TEXT: &str = "{{status}} - {{message}}\n";
+
+/// A format of error pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Html,
+    Json,
+    Text,
+}
+
+impl Format {
+    /// The format that the client prefers according to `accept`, which is plain text unless it
+    /// asks for HTML or JSON explicitly.
+    fn negotiate(accept: &str) -> Self {
+        let mut ranges: Vec<(&str, f32)> = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut params = range.split(';');
+                let media_type = params.next()?.trim();
+                let q = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                (q > 0.0).then_some((media_type, q))
+            })
+            .collect();
+        // Stable, so that ranges with the same quality stay in the client's order.
+        ranges.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        ranges
+            .into_iter()
+            .find_map(|(media_type, _)| match media_type {
+                "text/html" | "application/xhtml+xml" => Some(Format::Html),
+                "application/json" => Some(Format::Json),
+                "text/plain" => Some(Format::Text),
+                _ if media_type.ends_with("+json") => Some(Format::Json),
+                _ => None,
+            })
+            .unwrap_or(Format::Text)
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Html => "text/html; charset=utf-8",
+            Format::Json => "application/json",
+            Format::Text => "text/plain; charset=utf-8",
+        }
+    }
+
+    fn escape(self, value: &str) -> String {
+        match self {
+            Format::Html => escape(value),
+            Format::Json => {
+                let quoted = serde_json::to_string(value).unwrap_or_default();
+                quoted[1..quoted.len() - 1].to_string()
+            }
+            Format::Text => value.to_string(),
+        }
+    }
+}
+
+/// What the template of an error page is filled in with.
+#[derive(Debug, Clone, Default)]
+pub struct Failure {
+    pub host: String,
+    pub path: String,
+    pub request_id: String,
+    /// What to tell visitors, if not the usual message for the status.
+    pub message: Option<&'static str>,
+}
+
+impl Failure {
+    /// The ID of a request, from its `X-Request-Id` header if it has a sensible one, or a random
+    /// one otherwise.
+    pub fn request_id(headers: &HeaderMap) -> String {
+        headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= 128)
+            .filter(|id| id.bytes().all(|b| b.is_ascii_graphic()))
+            .map_or_else(|| format!("{:016x}", rand::random::<u64>()), str::to_string)
+    }
+}
+
+/// The error pages of a service (or the global ones), as absolute paths by status.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorPages(BTreeMap<u16, ErrorPageConfig>);
+
+impl ErrorPages {
+    /// The global error pages of `config`, overridden by the ones of `service` if there is one.
+    pub fn new(config: &Config, service: Option<&ServiceConfig>) -> Self {
+        let root = config.root_dir();
+        let mut pages: BTreeMap<u16, ErrorPageConfig> = BTreeMap::new();
+
+        let overrides = service.into_iter().flat_map(|service| &service.error_pages);
+        for (status, page) in config.error_pages.iter().chain(overrides) {
+            let Ok(status) = ErrorPageConfig::status(status) else {
+                continue;
+            };
+
+            let entry = pages.entry(status).or_default();
+            if let Some(html) = &page.html {
+                entry.html = Some(root.join(html));
+            }
+            if let Some(json) = &page.json {
+                entry.json = Some(root.join(json));
+            }
+        }
+
+        Self(pages)
+    }
+
+    /// Answers a request that failed with `status`, in the format that `accept` prefers.
+    pub async fn render(&self, status: StatusCode, accept: &str, failure: &Failure) -> Response {
+        let format = Format::negotiate(accept);
+
+        let page = self.0.get(&status.as_u16());
+        let path: Option<&PathBuf> = match format {
+            Format::Html => page.and_then(|page| page.html.as_ref()),
+            Format::Json => page.and_then(|page| page.json.as_ref()),
+            Format::Text => None,
+        };
+
+        let template = match path {
+            Some(path) => match tokio::fs::read_to_string(path).await {
+                Ok(template) => Some(template),
+                Err(err) => {
+                    tracing::warn!("Failed to read error page {}: {err}", path.display());
+                    None
+                }
+            },
+            None => None,
+        };
+        let template = template.as_deref().unwrap_or(match format {
+            Format::Html => HTML,
+            Format::Json => JSON,
+            Format::Text => TEXT,
+        });
+
+        let body = fill(template, format, status, failure);
+
+        let mut response = (
+            status,
+            [(header::CONTENT_TYPE, format.content_type())],
+            body,
+        )
+            .into_response();
+        if let Ok(id) = HeaderValue::from_str(&failure.request_id) {
+            response.headers_mut().insert(REQUEST_ID_HEADER, id);
+        }
+
+        response
+    }
+}
+
+/// What incipit tells visitors about a failure with `status`.
+fn message(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::NOT_FOUND => "Host not known by incipit",
+        StatusCode::BAD_GATEWAY => "The service couldn't be reached",
+        StatusCode::SERVICE_UNAVAILABLE => "The service isn't running",
+        StatusCode::GATEWAY_TIMEOUT => "The service took too long to answer",
+        _ => "Something went wrong",
+    }
+}
+
+/// Replaces the variables of `template`. Unknown variables are left as they are.
+fn fill(template: &str, format: Format, status: StatusCode, failure: &Failure) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find("}}") else {
+            break;
+        };
+
+        let value = match rest[2..end].trim() {
+            "status" => status.as_str().to_string(),
+            "reason" => status.canonical_reason().unwrap_or_default().to_string(),
+            "message" => failure.message.unwrap_or(message(status)).to_string(),
+            "host" => failure.host.clone(),
+            "path" => failure.path.clone(),
+            "request_id" => failure.request_id.clone(),
+            _ => {
+                filled.push_str(&rest[..end + 2]);
+                rest = &rest[end + 2..];
+                continue;
+            }
+        };
+
+        filled.push_str(&format.escape(&value));
+        rest = &rest[end + 2..];
+    }
+
+    filled.push_str(rest);
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_formats() {
+        assert_eq!(Format::negotiate(""), Format::Text);
+        assert_eq!(Format::negotiate("*/*"), Format::Text);
+        assert_eq!(
+            Format::negotiate("text/html,application/xhtml+xml,*/*;q=0.8"),
+            Format::Html
+        );
+        assert_eq!(Format::negotiate("application/json"), Format::Json);
+        assert_eq!(Format::negotiate("application/problem+json"), Format::Json);
+        assert_eq!(
+            Format::negotiate("text/html;q=0.5, application/json"),
+            Format::Json
+        );
+        assert_eq!(
+            Format::negotiate("application/json;q=0, text/plain"),
+            Format::Text
+        );
+    }
+
+    #[test]
+    fn fills_templates() {
+        let failure = Failure {
+            host: "<script>.example.com".into(),
+            path: "/a\"b'c".into(),
+            request_id: "42".into(),
+            message: None,
+        };
+        let fill = |template, format| fill(template, format, StatusCode::BAD_GATEWAY, &failure);
+
+        assert_eq!(
+            fill(
+                "{{ status }} {{reason}} on {{host}} {{unknown}} {{",
+                Format::Html
+            ),
+            "502 Bad Gateway on &lt;script&gt;.example.com {{unknown}} {{"
+        );
+        assert_eq!(
+            fill(JSON, Format::Json),
+            "{\"status\":502,\"error\":\"The service couldn't be reached\",\"request_id\":\"42\"}\n"
+        );
+        assert_eq!(fill("{{path}}", Format::Json), "/a\\\"b'c");
+        assert_eq!(fill("{{path}}", Format::Html), "/a&quot;b&#39;c");
+        assert_eq!(
+            fill(TEXT, Format::Text),
+            "502 - The service couldn't be reached\n"
+        );
+    }
+}
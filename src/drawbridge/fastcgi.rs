@@ -0,0 +1,557 @@
+//! Forwarding requests to FastCGI applications, such as PHP running in php-fpm.
+//!
+//! The whole response of the application is read before sending it to the client, since its
+//! headers come in the same stream as the body.
+
+use std::{collections::BTreeMap, net::SocketAddr, path::PathBuf};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request},
+    http::{header, request::Parts, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::{self, Context as _};
+use http_body_util::{BodyExt as _, LengthLimitError, Limited};
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWriteExt as _};
+
+use crate::config::{host, FastCgiConfig, StaticConfig};
+
+use super::{files, mapping::Target};
+
+const VERSION: u8 = 1;
+
+/// Types of records.
+const BEGIN_REQUEST: u8 = 1;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const STDERR: u8 = 7;
+
+/// Role of the application, which answers requests (as opposed to authorizing or filtering them).
+const RESPONDER: u16 = 1;
+
+/// Only one request is sent in each connection, so they can all have the same id.
+const REQUEST_ID: u16 = 1;
+
+/// Extension of the scripts that are run by the application.
+const SCRIPT_EXTENSION: &str = ".php";
+
+/// The script that a request runs, as in `SCRIPT_NAME`, `PATH_INFO` and `SCRIPT_FILENAME`.
+#[derive(Debug, PartialEq, Eq)]
+struct Script {
+    name: String,
+    path_info: String,
+    filename: PathBuf,
+}
+
+/// What a request is for.
+#[derive(Debug, PartialEq, Eq)]
+enum Resolved {
+    Script(Script),
+    /// A file in the document root, which is served as a static file.
+    File,
+    NotFound,
+}
+
+/// Forwards `request` to the FastCGI application at `address`.
+pub async fn forward(
+    request: Request,
+    address: &Target,
+    app: &FastCgiConfig,
+) -> eyre::Result<Response> {
+    let scheme = super::request_scheme(&request).to_string();
+    let (parts, body) = request.into_parts();
+
+    let script = match resolve(&parts.uri, app).await {
+        Resolved::Script(script) => script,
+        Resolved::File => {
+            let files = StaticConfig {
+                dir: app.root.clone(),
+                index: Vec::new(),
+                spa: false,
+                list_directories: false,
+            };
+            return files::serve(&parts, &files).await;
+        }
+        Resolved::NotFound => return Ok(files::not_found()),
+    };
+
+    tracing::trace!("Running {script:?} for {} at {address:?}", parts.uri);
+
+    let limit = usize::try_from(app.max_body_size).unwrap_or(usize::MAX);
+    let body = match Limited::new(body, limit).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(err) if err.is::<LengthLimitError>() => {
+            let status = StatusCode::PAYLOAD_TOO_LARGE;
+            return Ok((status, "413 - Request body too large").into_response());
+        }
+        Err(err) => return Err(eyre::eyre!(err)),
+    };
+    let params = params(&parts, &scheme, &script, app, body.len());
+
+    let mut stream = super::connect(address).await?;
+    stream.write_all(&encode(&params, &body)).await?;
+    let limit = usize::try_from(app.max_response_size).unwrap_or(usize::MAX);
+    let stdout = read_stdout(&mut stream, limit).await?;
+
+    response(&stdout)
+}
+
+/// Finds out what a request for `uri` runs (see [`FastCgiConfig`]).
+///
+/// Scripts only run if they exist, so that the application never gets to guess what to run (like
+/// PHP does with `cgi.fix_pathinfo`, which would run `/uploads/image.jpg` for
+/// `/uploads/image.jpg/x.php`).
+async fn resolve(uri: &Uri, app: &FastCgiConfig) -> Resolved {
+    let Some(relative) = files::relative_path(uri.path()) else {
+        return Resolved::NotFound;
+    };
+    let Ok(path) = percent_encoding::percent_decode_str(uri.path()).decode_utf8() else {
+        return Resolved::NotFound;
+    };
+
+    // The first segment that ends in `.php`, so `/a.php/b.php` runs `/a.php`.
+    let script_end = path
+        .match_indices(SCRIPT_EXTENSION)
+        .map(|(i, extension)| i + extension.len())
+        .find(|&end| path[end..].is_empty() || path[end..].starts_with('/'));
+
+    if let Some(end) = script_end {
+        if let Some(script) = script(app, &path[..end], &path[end..]).await {
+            return Resolved::Script(script);
+        }
+    } else if path.ends_with('/') {
+        if !app.index.is_empty() {
+            if let Some(script) = script(app, &format!("{path}{}", app.index), "").await {
+                return Resolved::Script(script);
+            }
+        }
+    } else if tokio::fs::metadata(app.root.join(relative)).await.is_ok() {
+        return Resolved::File;
+    }
+
+    let Some(front_controller) = &app.front_controller else {
+        return Resolved::NotFound;
+    };
+    let name = format!("/{}", front_controller.trim_start_matches('/'));
+
+    match script(app, &name, "").await {
+        Some(script) => Resolved::Script(script),
+        None => Resolved::NotFound,
+    }
+}
+
+/// The script at `name` (a decoded path, such as `/wiki/index.php`), if it is a file in the
+/// document root.
+async fn script(app: &FastCgiConfig, name: &str, path_info: &str) -> Option<Script> {
+    let filename = app.root.join(files::relative_decoded_path(name)?);
+
+    if !tokio::fs::metadata(&filename)
+        .await
+        .is_ok_and(|m| m.is_file())
+    {
+        return None;
+    }
+
+    Some(Script {
+        name: name.to_string(),
+        path_info: path_info.to_string(),
+        filename,
+    })
+}
+
+/// The CGI parameters of a request, overridden by [`FastCgiConfig::params`].
+fn params(
+    parts: &Parts,
+    scheme: &str,
+    script: &Script,
+    app: &FastCgiConfig,
+    content_length: usize,
+) -> BTreeMap<String, String> {
+    let root = app.root.to_string_lossy();
+    let authority = super::request_authority(&parts.uri, &parts.headers).unwrap_or_default();
+    let (server_name, port) = host::split_port(authority).unwrap_or((authority, None));
+    let port = port.unwrap_or(if scheme == "https" { 443 } else { 80 });
+
+    let mut params: BTreeMap<String, String> = [
+        ("GATEWAY_INTERFACE", "CGI/1.1".to_string()),
+        ("SERVER_SOFTWARE", "incipit".to_string()),
+        ("SERVER_PROTOCOL", "HTTP/1.1".to_string()),
+        ("SERVER_NAME", server_name.to_string()),
+        ("SERVER_PORT", port.to_string()),
+        ("REQUEST_SCHEME", scheme.to_string()),
+        ("REQUEST_METHOD", parts.method.to_string()),
+        (
+            "REQUEST_URI",
+            parts
+                .uri
+                .path_and_query()
+                .map_or("/", |path| path.as_str())
+                .to_string(),
+        ),
+        (
+            "QUERY_STRING",
+            parts.uri.query().unwrap_or_default().to_string(),
+        ),
+        ("DOCUMENT_ROOT", root.to_string()),
+        (
+            "DOCUMENT_URI",
+            format!("{}{}", script.name, script.path_info),
+        ),
+        ("SCRIPT_NAME", script.name.clone()),
+        (
+            "SCRIPT_FILENAME",
+            script.filename.to_string_lossy().into_owned(),
+        ),
+        ("CONTENT_LENGTH", content_length.to_string()),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect();
+
+    if !script.path_info.is_empty() {
+        params.insert("PATH_INFO".into(), script.path_info.clone());
+    }
+    if scheme == "https" {
+        params.insert("HTTPS".into(), "on".into());
+    }
+    if let Some(ConnectInfo(addr)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+        params.insert("REMOTE_ADDR".into(), addr.ip().to_string());
+        params.insert("REMOTE_PORT".into(), addr.port().to_string());
+    }
+    if let Some(content_type) = parts.headers.get(header::CONTENT_TYPE) {
+        params.insert(
+            "CONTENT_TYPE".into(),
+            String::from_utf8_lossy(content_type.as_bytes()).into_owned(),
+        );
+    }
+
+    for (name, value) in &parts.headers {
+        // `Proxy` would become `HTTP_PROXY`, which many programs use as their proxy (httpoxy).
+        if [header::CONTENT_TYPE, header::CONTENT_LENGTH].contains(name) || name == "proxy" {
+            continue;
+        }
+
+        let name = format!(
+            "HTTP_{}",
+            name.as_str().to_ascii_uppercase().replace('-', "_")
+        );
+        // HTTP/2 clients send each cookie in its own header, which are joined like in a single
+        // one (RFC 9113, section 8.2.3).
+        let separator = if name == "HTTP_COOKIE" { "; " } else { ", " };
+        let value = String::from_utf8_lossy(value.as_bytes());
+        params
+            .entry(name)
+            .and_modify(|existing| {
+                existing.push_str(separator);
+                existing.push_str(&value);
+            })
+            .or_insert_with(|| value.into_owned());
+    }
+
+    // HTTP/2 requests have their host in the URI instead of in a `Host` header.
+    if !authority.is_empty() {
+        params
+            .entry("HTTP_HOST".into())
+            .or_insert_with(|| authority.to_string());
+    }
+
+    params.extend(app.params.clone());
+    params
+}
+
+/// Encodes a whole request, with its parameters and body.
+fn encode(params: &BTreeMap<String, String>, body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    let mut begin = RESPONDER.to_be_bytes().to_vec();
+    // No flags, so the application closes the connection when it's done.
+    begin.extend([0; 6]);
+    push_record(&mut out, BEGIN_REQUEST, &begin);
+
+    let mut encoded = Vec::new();
+    for (name, value) in params {
+        push_length(&mut encoded, name.len());
+        push_length(&mut encoded, value.len());
+        encoded.extend_from_slice(name.as_bytes());
+        encoded.extend_from_slice(value.as_bytes());
+    }
+    push_stream(&mut out, PARAMS, &encoded);
+    push_stream(&mut out, STDIN, body);
+
+    out
+}
+
+/// Pushes the length of a name or value, which takes one byte if it's short and four otherwise.
+fn push_length(out: &mut Vec<u8>, len: usize) {
+    match u8::try_from(len) {
+        Ok(len) if len < 0x80 => out.push(len),
+        _ => out.extend((len as u32 | 0x8000_0000).to_be_bytes()),
+    }
+}
+
+/// Pushes `content` as records of `kind`, followed by an empty one to end the stream.
+fn push_stream(out: &mut Vec<u8>, kind: u8, content: &[u8]) {
+    for chunk in content.chunks(u16::MAX.into()) {
+        push_record(out, kind, chunk);
+    }
+    push_record(out, kind, &[]);
+}
+
+/// Pushes a single record, whose content has to fit in a `u16`.
+fn push_record(out: &mut Vec<u8>, kind: u8, content: &[u8]) {
+    let len = u16::try_from(content.len()).expect("Records are split into chunks that fit");
+
+    out.extend([VERSION, kind]);
+    out.extend(REQUEST_ID.to_be_bytes());
+    out.extend(len.to_be_bytes());
+    // No padding, and a reserved byte.
+    out.extend([0, 0]);
+    out.extend_from_slice(content);
+}
+
+/// Reads the records that the application sends until the end of the request, returning what it
+/// wrote to its standard output (which can't be longer than `limit`). What it writes to its
+/// standard error is logged.
+async fn read_stdout(stream: &mut (impl AsyncRead + Unpin), limit: usize) -> eyre::Result<Vec<u8>> {
+    let mut stdout = Vec::new();
+
+    loop {
+        let mut header = [0; 8];
+        stream
+            .read_exact(&mut header)
+            .await
+            .wrap_err("The FastCGI application closed the connection before answering")?;
+
+        let len = u16::from_be_bytes([header[4], header[5]]).into();
+        let padding = usize::from(header[6]);
+        let mut content = vec![0; len + padding];
+        stream.read_exact(&mut content).await?;
+        content.truncate(len);
+
+        match header[1] {
+            STDOUT => {
+                eyre::ensure!(
+                    stdout.len() + content.len() <= limit,
+                    "The FastCGI application sent a response larger than {limit} bytes"
+                );
+                stdout.extend_from_slice(&content);
+            }
+            STDERR => tracing::warn!(
+                "FastCGI application: {}",
+                String::from_utf8_lossy(&content).trim_end()
+            ),
+            END_REQUEST => {
+                // The status of the protocol (after the status of the application).
+                if let Some(&status) = content.get(4).filter(|&&status| status != 0) {
+                    eyre::bail!("The FastCGI application rejected the request ({status})");
+                }
+
+                return Ok(stdout);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Converts the output of a CGI script (headers, an empty line and the body) into a response.
+fn response(stdout: &[u8]) -> eyre::Result<Response> {
+    let crlf = stdout
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| (i, i + 4));
+    let lf = stdout
+        .windows(2)
+        .position(|w| w == b"\n\n")
+        .map(|i| (i, i + 2));
+    let Some((head_end, body_start)) = [crlf, lf].into_iter().flatten().min() else {
+        eyre::bail!("The FastCGI application didn't send any headers");
+    };
+
+    let head = std::str::from_utf8(&stdout[..head_end])
+        .wrap_err("The FastCGI application sent invalid headers")?;
+
+    let mut response = Response::builder();
+    let mut status = None;
+    let mut redirect = false;
+    for line in head.lines() {
+        let Some((name, value)) = line.split_once(':') else {
+            eyre::bail!("The FastCGI application sent an invalid header `{line}`");
+        };
+        let (name, value) = (name.trim(), value.trim());
+
+        if name.eq_ignore_ascii_case("status") {
+            let code = value.split_whitespace().next().unwrap_or_default();
+            status = Some(StatusCode::from_bytes(code.as_bytes())?);
+            continue;
+        }
+
+        redirect |= name.eq_ignore_ascii_case("location");
+        response = response.header(name, value);
+    }
+
+    // CGI scripts can redirect with just a `Location`.
+    let status = status.unwrap_or(if redirect {
+        StatusCode::FOUND
+    } else {
+        StatusCode::OK
+    });
+
+    let body = Body::from(stdout[body_start..].to_vec());
+    Ok(response.status(status).body(body)?.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(root: &std::path::Path) -> FastCgiConfig {
+        FastCgiConfig {
+            root: root.to_path_buf(),
+            index: "index.php".into(),
+            front_controller: None,
+            params: BTreeMap::new(),
+            max_body_size: 1024,
+            max_response_size: 1024,
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_scripts() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        for file in [
+            "style.css",
+            "index.php",
+            "wiki/index.php",
+            "photos/index.php",
+        ] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, "")?;
+        }
+        std::fs::create_dir(dir.path().join("uploads"))?;
+        let mut app = app(dir.path());
+
+        let resolve = |path: &'static str, app: &FastCgiConfig| {
+            let app = app.clone();
+            async move { resolve(&path.parse().unwrap(), &app).await }
+        };
+        let script = |name: &str, path_info: &str| {
+            Resolved::Script(Script {
+                name: name.into(),
+                path_info: path_info.into(),
+                filename: dir.path().join(name.trim_start_matches('/')),
+            })
+        };
+
+        assert_eq!(
+            resolve("/wiki/index.php/Main_Page", &app).await,
+            script("/wiki/index.php", "/Main_Page")
+        );
+        assert_eq!(
+            resolve("/photos/", &app).await,
+            script("/photos/index.php", "")
+        );
+        assert_eq!(resolve("/style.css", &app).await, Resolved::File);
+        assert_eq!(resolve("/a.phpx", &app).await, Resolved::NotFound);
+        assert_eq!(resolve("/../etc/x.php", &app).await, Resolved::NotFound);
+
+        // Scripts that don't exist don't run, even if a file in their path does.
+        assert_eq!(resolve("/missing.php", &app).await, Resolved::NotFound);
+        assert_eq!(resolve("/style.css/x.php", &app).await, Resolved::NotFound);
+        assert_eq!(resolve("/uploads/", &app).await, Resolved::NotFound);
+
+        app.front_controller = Some("index.php".into());
+        assert_eq!(resolve("/users/1", &app).await, script("/index.php", ""));
+        assert_eq!(
+            resolve("/missing.php", &app).await,
+            script("/index.php", "")
+        );
+        assert_eq!(resolve("/uploads/", &app).await, script("/index.php", ""));
+
+        app.index = String::new();
+        assert_eq!(resolve("/photos/", &app).await, script("/index.php", ""));
+
+        app.front_controller = Some("missing.php".into());
+        assert_eq!(resolve("/users/1", &app).await, Resolved::NotFound);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn limits_responses() {
+        let mut records = vec![1, STDOUT, 0, 1, 0, 10, 0, 0];
+        records.extend([b'a'; 10]);
+        records.extend([1, END_REQUEST, 0, 1, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        assert!(read_stdout(&mut records.as_slice(), 10).await.is_ok());
+        assert!(read_stdout(&mut records.as_slice(), 9).await.is_err());
+    }
+
+    #[test]
+    fn sets_params() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let app = app(dir.path());
+        let script = Script {
+            name: "/index.php".into(),
+            path_info: String::new(),
+            filename: dir.path().join("index.php"),
+        };
+
+        // Like an HTTP/2 request, with the host in the URI and a header for each cookie.
+        let (parts, ()) = Request::builder()
+            .uri("https://wiki.example.com:8443/index.php")
+            .header(header::COOKIE, "session=abc")
+            .header(header::COOKIE, "theme=dark")
+            .header(header::ACCEPT, "text/html")
+            .header(header::ACCEPT, "application/json")
+            .body(())?
+            .into_parts();
+        let params = params(&parts, "https", &script, &app, 0);
+
+        assert_eq!(params["SERVER_NAME"], "wiki.example.com");
+        assert_eq!(params["SERVER_PORT"], "8443");
+        assert_eq!(params["HTTP_HOST"], "wiki.example.com:8443");
+        assert_eq!(params["HTTP_COOKIE"], "session=abc; theme=dark");
+        assert_eq!(params["HTTP_ACCEPT"], "text/html, application/json");
+
+        Ok(())
+    }
+
+    #[test]
+    fn encodes_requests() {
+        let params = BTreeMap::from([("A".to_string(), "x".repeat(200))]);
+        let encoded = encode(&params, b"body");
+
+        // Begin the request as a responder.
+        assert_eq!(encoded[..8], [1, BEGIN_REQUEST, 0, 1, 0, 8, 0, 0]);
+        assert_eq!(encoded[8..10], [0, 1]);
+
+        // A short name and a long value, then the end of the parameters.
+        let params = &encoded[16..];
+        assert_eq!(params[..8], [1, PARAMS, 0, 1, 0, 206, 0, 0]);
+        assert_eq!(params[8..14], [1, 0x80, 0, 0, 200, b'A']);
+        let rest = &params[8 + 206..];
+        assert_eq!(rest[..8], [1, PARAMS, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(rest[8..16], [1, STDIN, 0, 1, 0, 4, 0, 0]);
+        assert_eq!(&rest[16..20], b"body");
+        assert_eq!(rest[20..], [1, STDIN, 0, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn parses_responses() -> eyre::Result<()> {
+        let response =
+            super::response(b"Status: 404 Not Found\r\nContent-Type: text/html\r\n\r\nNope")?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["content-type"], "text/html");
+
+        let response = super::response(b"Location: /login\n\n")?;
+        assert_eq!(response.status(), StatusCode::FOUND);
+
+        assert!(super::response(b"no headers").is_err());
+
+        Ok(())
+    }
+}
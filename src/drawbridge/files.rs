@@ -0,0 +1,494 @@
+//! Serving directories of static files, for services with `static`.
+//!
+//! Files are served with `ETag` and `Last-Modified` (and answer conditional requests with a 304),
+//! support single range requests, and are replaced by their precompressed `.br` or `.gz` versions
+//! when those exist and the client accepts them.
+
+use std::{
+    fs::Metadata,
+    io::SeekFrom,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt as _, AsyncSeekExt as _},
+};
+
+use crate::{config::StaticConfig, util::escape};
+
+/// Precompressed versions of files, as their content encoding and extension, in order of
+/// preference.
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// Size of the chunks that files are sent in.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Characters that are escaped in the links of directory listings.
+const LINK: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
+/// Answers a request (of which only the head is needed) with the files in `site`.
+pub async fn serve(request: &Parts, site: &StaticConfig) -> eyre::Result<Response> {
+    if request.method != Method::GET && request.method != Method::HEAD {
+        return Ok((
+            StatusCode::METHOD_NOT_ALLOWED,
+            [(header::ALLOW, "GET, HEAD")],
+            "405 - Static files can only be read",
+        )
+            .into_response());
+    }
+
+    let uri_path = request.uri.path();
+    let Some(relative) = relative_path(uri_path) else {
+        return Ok(not_found());
+    };
+
+    let path = site.dir.join(relative);
+    match tokio::fs::metadata(&path).await {
+        Ok(metadata) if metadata.is_dir() => {
+            // Relative links in the index are resolved from the directory, so it needs to end in
+            // a slash. The redirect is relative too, in case a path prefix was stripped.
+            if !uri_path.ends_with('/') {
+                let name = uri_path.rsplit('/').next().unwrap_or_default();
+                let location = match request.uri.query() {
+                    Some(query) => format!("{name}/?{query}"),
+                    None => format!("{name}/"),
+                };
+                return Ok((
+                    StatusCode::MOVED_PERMANENTLY,
+                    [(header::LOCATION, location)],
+                )
+                    .into_response());
+            }
+
+            if let Some(index) = find_index(&path, site).await {
+                return serve_file(request, &index).await;
+            }
+
+            if site.list_directories {
+                return list_directory(&path, uri_path).await;
+            }
+        }
+        Ok(_) => return serve_file(request, &path).await,
+        Err(_) => {}
+    }
+
+    if site.spa {
+        if let Some(index) = find_index(&site.dir, site).await {
+            return serve_file(request, &index).await;
+        }
+    }
+
+    Ok(not_found())
+}
+
+/// Marks the 404s of files that don't exist, so that they get the error page of the service
+/// rather than an empty body (see [`super::ErrorPages`]).
+#[derive(Debug, Clone, Copy)]
+pub(super) struct NotFound;
+
+pub(super) fn not_found() -> Response {
+    let mut response = StatusCode::NOT_FOUND.into_response();
+    response.extensions_mut().insert(NotFound);
+    response
+}
+
+/// Converts the path of a request into a path relative to the served directory.
+///
+/// Returns `None` for paths that try to leave the directory (with `..`) or that have hidden
+/// files or directories (starting with `.`, such as `.git` or `.env`), except for `.well-known`.
+pub(super) fn relative_path(uri_path: &str) -> Option<PathBuf> {
+    let decoded = percent_encoding::percent_decode_str(uri_path)
+        .decode_utf8()
+        .ok()?;
+
+    relative_decoded_path(&decoded)
+}
+
+/// Like [`relative_path`], for a path that is already percent-decoded.
+pub(super) fn relative_decoded_path(decoded: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".well-known" => path.push(segment),
+            _ if segment.starts_with('.') || segment.contains(['\\', '\0']) => return None,
+            _ => path.push(segment),
+        }
+    }
+
+    Some(path)
+}
+
+/// Returns the first index file of `site` that exists in `dir`.
+async fn find_index(dir: &Path, site: &StaticConfig) -> Option<PathBuf> {
+    for name in &site.index {
+        let path = dir.join(name);
+        if tokio::fs::metadata(&path).await.is_ok_and(|m| m.is_file()) {
+            return Some(path);
+        }
+    }
+
+    None
+}
+
+async fn serve_file(request: &Parts, path: &Path) -> eyre::Result<Response> {
+    let content_type = mime_guess::from_path(path).first_or_octet_stream();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(content_type.as_ref())?,
+    );
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+
+    let (file, metadata) = match precompressed(&request.headers, path).await {
+        Some((encoding, file, metadata)) => {
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+            (file, metadata)
+        }
+        None => {
+            let file = File::open(path).await?;
+            let metadata = file.metadata().await?;
+            (file, metadata)
+        }
+    };
+
+    let len = metadata.len();
+    let modified = metadata.modified().ok().map(whole_seconds);
+    let etag = etag(&metadata);
+
+    headers.insert(header::ETAG, HeaderValue::from_str(&etag)?);
+    if let Some(modified) = modified {
+        headers.insert(
+            header::LAST_MODIFIED,
+            HeaderValue::from_str(&httpdate::fmt_http_date(modified))?,
+        );
+    }
+
+    if is_not_modified(&request.headers, &etag, modified) {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+
+    let (status, range) = match requested_range(&request.headers, &etag, modified, len) {
+        Requested::Whole => (StatusCode::OK, 0..len),
+        Requested::Part(range) => {
+            let content_range = format!("bytes {}-{}/{len}", range.start, range.end - 1);
+            headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&content_range)?,
+            );
+            (StatusCode::PARTIAL_CONTENT, range)
+        }
+        Requested::Unsatisfiable => {
+            headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{len}"))?,
+            );
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+        }
+    };
+
+    headers.insert(
+        header::CONTENT_LENGTH,
+        HeaderValue::from(range.end - range.start),
+    );
+
+    let body = match request.method {
+        Method::HEAD => Body::empty(),
+        _ => stream(file, range).await?,
+    };
+
+    Ok((status, headers, body).into_response())
+}
+
+/// Opens the precompressed version of `path` that the client prefers, if there is one.
+async fn precompressed(headers: &HeaderMap, path: &Path) -> Option<(&'static str, File, Metadata)> {
+    for (encoding, extension) in PRECOMPRESSED {
+        if !accepts_encoding(headers, encoding) {
+            continue;
+        }
+
+        let mut compressed = path.as_os_str().to_owned();
+        compressed.push(".");
+        compressed.push(extension);
+
+        let Ok(file) = File::open(&compressed).await else {
+            continue;
+        };
+
+        match file.metadata().await {
+            Ok(metadata) if metadata.is_file() => return Some((encoding, file, metadata)),
+            _ => continue,
+        }
+    }
+
+    None
+}
+
+/// Whether `Accept-Encoding` includes `encoding` (without `q=0`, which means it isn't accepted).
+fn accepts_encoding(headers: &HeaderMap, encoding: &str) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|item| {
+            let mut params = item.split(';');
+            let name = params.next().unwrap_or_default().trim();
+            let rejected = params.any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    == Some(0.0)
+            });
+
+            name.eq_ignore_ascii_case(encoding) && !rejected
+        })
+}
+
+/// A strong `ETag` from the size and modification time of a file.
+fn etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    format!("\"{:x}-{:x}\"", metadata.len(), modified.as_nanos())
+}
+
+/// HTTP dates only have seconds, so modification times have to be compared without the rest.
+fn whole_seconds(time: SystemTime) -> SystemTime {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+/// Whether the client already has the file, according to `If-None-Match` or (if that isn't set)
+/// `If-Modified-Since`.
+fn is_not_modified(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        return if_none_match.to_str().is_ok_and(|tags| {
+            tags.split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.trim_start_matches("W/") == etag
+            })
+        });
+    }
+
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|since| since.to_str().ok())
+        .and_then(|since| httpdate::parse_http_date(since).ok());
+
+    matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+}
+
+/// The part of a file that a request asks for.
+#[derive(Debug, PartialEq, Eq)]
+enum Requested {
+    Whole,
+    /// A range of bytes, with an exclusive end that is within the file.
+    Part(Range<u64>),
+    Unsatisfiable,
+}
+
+/// Reads the `Range` header of a request for a file of `len` bytes.
+///
+/// Only single ranges are supported, requests for several ranges get the whole file (which is
+/// allowed). So do requests whose `If-Range` doesn't match the file anymore.
+fn requested_range(
+    headers: &HeaderMap,
+    etag: &str,
+    modified: Option<SystemTime>,
+    len: u64,
+) -> Requested {
+    let Some(range) = headers
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok())
+    else {
+        return Requested::Whole;
+    };
+
+    if let Some(if_range) = headers.get(header::IF_RANGE) {
+        let if_range = if_range.to_str().unwrap_or_default();
+        let date = httpdate::parse_http_date(if_range).ok();
+        if if_range != etag && (date.is_none() || date != modified) {
+            return Requested::Whole;
+        }
+    }
+
+    let Some((start, end)) = range
+        .strip_prefix("bytes=")
+        .filter(|spec| !spec.contains(','))
+        .and_then(|spec| spec.trim().split_once('-'))
+    else {
+        return Requested::Whole;
+    };
+
+    let range = match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return Requested::Unsatisfiable,
+            Ok(suffix) => len.saturating_sub(suffix)..len,
+            Err(_) => return Requested::Whole,
+        },
+        (start, "") => match start.parse() {
+            Ok(start) => start..len,
+            Err(_) => return Requested::Whole,
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => start..len.min(end + 1),
+            _ => return Requested::Whole,
+        },
+    };
+
+    if range.start >= len {
+        return Requested::Unsatisfiable;
+    }
+
+    Requested::Part(range)
+}
+
+/// Streams the bytes of `file` in `range`.
+async fn stream(mut file: File, range: Range<u64>) -> std::io::Result<Body> {
+    file.seek(SeekFrom::Start(range.start)).await?;
+
+    let remaining = range.end - range.start;
+    let chunks =
+        futures::stream::try_unfold((file, remaining), |(mut file, remaining)| async move {
+            if remaining == 0 {
+                return Ok(None);
+            }
+
+            let mut chunk = vec![0; remaining.min(CHUNK_SIZE) as usize];
+            let read = file.read(&mut chunk).await?;
+            if read == 0 {
+                // The file got shorter since it was opened.
+                return Ok(None);
+            }
+
+            chunk.truncate(read);
+            Ok::<_, std::io::Error>(Some((Bytes::from(chunk), (file, remaining - read as u64))))
+        });
+
+    Ok(Body::from_stream(chunks))
+}
+
+/// Lists the files in `dir`, which is at `uri_path`, as an HTML page.
+async fn list_directory(dir: &Path, uri_path: &str) -> eyre::Result<Response> {
+    let mut names = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+
+        if entry.file_type().await?.is_dir() {
+            names.push(format!("{name}/"));
+        } else {
+            names.push(name);
+        }
+    }
+    names.sort();
+
+    let title = format!("Index of {}", escape(uri_path));
+    let mut html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{title}</title></head>\
+         <body><h1>{title}</h1><ul>"
+    );
+    if uri_path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>");
+    }
+    for name in names {
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>",
+            utf8_percent_encode(&name, LINK),
+            escape(&name)
+        ));
+    }
+    html.push_str("</ul></body></html>");
+
+    Ok(axum::response::Html(html).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths() {
+        let relative = |path| relative_path(path).map(|p| p.display().to_string());
+        assert_eq!(relative("/").as_deref(), Some(""));
+        assert_eq!(
+            relative("/assets/./app%20v2.js").as_deref(),
+            Some("assets/app v2.js")
+        );
+        assert_eq!(
+            relative("/.well-known/security.txt").as_deref(),
+            Some(".well-known/security.txt")
+        );
+        assert_eq!(relative("/../etc/passwd"), None);
+        assert_eq!(relative("/a/%2e%2e/%2e%2e/etc/passwd"), None);
+        assert_eq!(relative("/.git/config"), None);
+    }
+
+    #[test]
+    fn ranges() {
+        let range = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::RANGE, value.parse().unwrap());
+            requested_range(&headers, "\"tag\"", None, 100)
+        };
+
+        assert_eq!(range("bytes=0-9"), Requested::Part(0..10));
+        assert_eq!(range("bytes=90-"), Requested::Part(90..100));
+        assert_eq!(range("bytes=-10"), Requested::Part(90..100));
+        assert_eq!(range("bytes=50-1000"), Requested::Part(50..100));
+        assert_eq!(range("bytes=100-"), Requested::Unsatisfiable);
+        assert_eq!(range("bytes=0-1,5-6"), Requested::Whole);
+        assert_eq!(range("lines=1-2"), Requested::Whole);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, "bytes=0-9".parse().unwrap());
+        headers.insert(header::IF_RANGE, "\"old\"".parse().unwrap());
+        assert_eq!(
+            requested_range(&headers, "\"tag\"", None, 100),
+            Requested::Whole
+        );
+    }
+
+    #[test]
+    fn accepted_encodings() {
+        let accepts = |value: &str, encoding| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT_ENCODING, value.parse().unwrap());
+            accepts_encoding(&headers, encoding)
+        };
+
+        assert!(accepts("gzip, deflate, br", "br"));
+        assert!(accepts("GZIP;q=0.5", "gzip"));
+        assert!(!accepts("gzip;q=0, br", "gzip"));
+        assert!(!accepts("identity", "gzip"));
+    }
+}
@@ -0,0 +1,209 @@
+//! Maintenance mode of services (see [`crate::config::MaintenanceConfig`]).
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{PoisonError, RwLock},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{header, HeaderMap, HeaderValue},
+};
+
+use crate::config::{IpRange, ServiceConfig};
+
+use super::request_cookies;
+
+/// Cookie that lets clients with the bypass token of a service through its maintenance mode.
+pub const BYPASS_COOKIE: &str = "incipit-maintenance";
+
+/// The maintenance mode of a service, as configured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Maintenance {
+    service: String,
+    enabled: bool,
+    retry_after: u64,
+    allow: Vec<IpRange>,
+    bypass_token: Option<String>,
+}
+
+impl Maintenance {
+    pub fn new(service: &ServiceConfig) -> Self {
+        let config = service.maintenance.clone().unwrap_or_default();
+
+        Self {
+            service: service.name.clone(),
+            enabled: config.enabled,
+            retry_after: config.retry_after,
+            allow: config.allowed().unwrap_or_default(),
+            bypass_token: config.bypass_token,
+        }
+    }
+
+    /// When clients should try again, in seconds.
+    pub fn retry_after(&self) -> u64 {
+        self.retry_after
+    }
+
+    /// Whether `request` reaches the service even during maintenance.
+    fn lets_through(&self, request: &Request) -> bool {
+        let ip = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        if ip.is_some_and(|ip: IpAddr| self.allow.iter().any(|range| range.contains(ip))) {
+            return true;
+        }
+
+        self.bypass_token.as_deref().is_some_and(|token| {
+            request_cookies(request.headers())
+                .iter()
+                .any(|&(name, value)| name == BYPASS_COOKIE && constant_time_eq(value, token))
+        })
+    }
+}
+
+/// Compares `a` and `b` in a time that only depends on their lengths, so that the bypass token
+/// can't be guessed from how long it takes to reject wrong ones.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Removes the bypass cookie from the `Cookie` headers, so that the token doesn't reach the
+/// service.
+pub(super) fn strip_bypass_cookie(headers: &mut HeaderMap) {
+    let cookies: Vec<&str> = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .collect();
+    let kept: Vec<&str> = cookies
+        .iter()
+        .copied()
+        .filter(|cookie| cookie.split('=').next().map(str::trim) != Some(BYPASS_COOKIE))
+        .collect();
+    if kept.len() == cookies.len() {
+        return;
+    }
+
+    let kept = kept
+        .iter()
+        .map(|cookie| cookie.trim())
+        .collect::<Vec<_>>()
+        .join("; ");
+    let value = HeaderValue::from_str(&kept).ok();
+
+    headers.remove(header::COOKIE);
+    if let Some(value) = value.filter(|_| !kept.is_empty()) {
+        headers.insert(header::COOKIE, value);
+    }
+}
+
+/// Services that were put in or out of maintenance while incipit runs (from the dashboard), which
+/// overrides their config.
+#[derive(Debug, Default)]
+pub struct MaintenanceMode(RwLock<HashMap<String, bool>>);
+
+impl MaintenanceMode {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Puts `service` in maintenance, or takes it out of it.
+    pub fn set(&self, service: &str, enabled: bool) {
+        self.0
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(service.to_string(), enabled);
+    }
+
+    /// Whether `service` is in maintenance, `configured` being whether its config says so.
+    pub fn is_enabled(&self, service: &str, configured: bool) -> bool {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(service)
+            .copied()
+            .unwrap_or(configured)
+    }
+
+    /// Whether `request` has to get the maintenance page of its service instead of reaching it.
+    pub fn blocks(&self, maintenance: &Maintenance, request: &Request) -> bool {
+        self.is_enabled(&maintenance.service, maintenance.enabled)
+            && !maintenance.lets_through(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::MaintenanceConfig;
+
+    use super::*;
+
+    #[test]
+    fn lets_some_requests_through() {
+        let service = ServiceConfig {
+            name: "app".into(),
+            maintenance: Some(MaintenanceConfig {
+                enabled: true,
+                allow: vec!["10.0.0.0/8".into()],
+                bypass_token: Some("s3cret".into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let maintenance = Maintenance::new(&service);
+        let mode = MaintenanceMode::new();
+
+        let request = |ip: [u8; 4], cookie: &str| {
+            let mut request = Request::builder()
+                .header("cookie", cookie)
+                .body(Default::default())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((ip, 1234))));
+            request
+        };
+
+        assert!(mode.blocks(&maintenance, &request([192, 168, 1, 2], "")));
+        assert!(!mode.blocks(&maintenance, &request([10, 1, 2, 3], "")));
+        assert!(!mode.blocks(
+            &maintenance,
+            &request([192, 168, 1, 2], "a=b; incipit-maintenance=s3cret")
+        ));
+        assert!(mode.blocks(
+            &maintenance,
+            &request([192, 168, 1, 2], "incipit-maintenance=guess")
+        ));
+
+        mode.set("app", false);
+        assert!(!mode.blocks(&maintenance, &request([192, 168, 1, 2], "")));
+    }
+
+    #[test]
+    fn strips_the_bypass_cookie() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            header::COOKIE,
+            "a=b; incipit-maintenance=s3cret".parse().unwrap(),
+        );
+        headers.append(header::COOKIE, "c=d".parse().unwrap());
+        strip_bypass_cookie(&mut headers);
+        assert_eq!(headers[header::COOKIE], "a=b; c=d");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            "incipit-maintenance=s3cret".parse().unwrap(),
+        );
+        strip_bypass_cookie(&mut headers);
+        assert!(headers.get(header::COOKIE).is_none());
+    }
+}
@@ -1,18 +1,53 @@
-use std::net::SocketAddr;
-use std::sync::{Arc, RwLock};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
 
-use crate::config::Config;
+use axum::http::StatusCode;
 
-/// The target to a mapping, which can be either a socket address, incipit itself or unknown
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+use crate::config::{host, Config, FastCgiConfig, ServiceConfig, StaticConfig};
+
+use super::{ErrorPages, Maintenance, RoutingTable, Split};
+
+/// The target to a mapping, which can be either a socket address, a host name and port, a unix
+/// socket, a directory of static files, a FastCGI application, a redirect, incipit itself or
+/// unknown
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
 pub enum Target {
     Socket(SocketAddr),
+    /// A host name, resolved with DNS when connecting.
+    Hostname(String, u16),
+    /// Path of a unix domain socket.
+    Unix(PathBuf),
+    /// Files served by incipit itself, with [`StaticConfig::dir`] as an absolute path.
+    Static(StaticConfig),
+    /// A FastCGI application at an address (which is another target), with
+    /// [`FastCgiConfig::root`] as an absolute path.
+    FastCgi(Box<Target>, FastCgiConfig),
+    Redirect(Redirect),
     Incipit,
     #[default]
     Unknown,
 }
 
+/// A redirect, answered by incipit itself.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Redirect {
+    /// Where to redirect to: a URL, a path on the same host, or a host and path without a scheme
+    /// (to keep the scheme of the request). The query of the request is added if this doesn't
+    /// have one.
+    pub to: String,
+    pub status: StatusCode,
+}
+
 impl Target {
+    /// Whether the target is a service that requests are forwarded to.
+    pub fn is_upstream(&self) -> bool {
+        matches!(
+            self,
+            Target::Socket(_) | Target::Hostname(..) | Target::Unix(_)
+        )
+    }
+
     /// Creates a new [`Target::Addr`] on 0.0.0.0 with the specified port
     pub fn port(port: u16) -> Self {
         let addr = ([0, 0, 0, 0], port).into();
@@ -20,56 +55,130 @@ impl Target {
     }
 }
 
+/// Where a request goes, along with how it has to be changed on the way.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Route {
+    pub target: Target,
+
+    /// The part of the host matched by a wildcard (such as `alice` in `alice.example.com` for
+    /// `*.example.com`).
+    pub subdomain: Option<String>,
+
+    /// Path prefix of the service that was matched, if any, sent in the `X-Forwarded-Prefix`
+    /// header.
+    pub prefix: Option<String>,
+
+    /// Whether to remove `prefix` from the path before forwarding.
+    pub strip_prefix: bool,
+
+    /// The variants of the service, which `target` is only the default of.
+    pub split: Option<Arc<Split>>,
+
+    /// The error pages of the service, if they aren't the global ones.
+    pub error_pages: Option<Arc<ErrorPages>>,
+
+    /// The maintenance mode of the service, for services of the config.
+    pub maintenance: Option<Arc<Maintenance>>,
+}
+
+impl From<Target> for Route {
+    fn from(target: Target) -> Self {
+        Route {
+            target,
+            subdomain: None,
+            prefix: None,
+            strip_prefix: false,
+            split: None,
+            error_pages: None,
+            maintenance: None,
+        }
+    }
+}
+
 /// A trait for mapping hosts to addresses.
 ///
-/// This is used to determine where to forward requests based on the host header.
+/// This is used to determine where to forward requests based on the host header (and the path).
+/// The host is normalized (see [`host::normalize`]).
 ///
-/// Returns `None` when the host is not known.
+/// Returns [`Target::Unknown`] when the host is not known.
 pub trait HostMapping {
-    fn route(&self, host: &str) -> Target;
+    fn route(&self, host: &str, path: &str) -> Route;
 }
 
+/// Builds a [`RoutingTable`] for every request, so it is only meant for one-off lookups.
 impl HostMapping for Config {
-    fn route(&self, host: &str) -> Target {
-        if self
-            .incipit_host
-            .as_ref()
-            .map(|ih| ih == host)
-            .unwrap_or(false)
-        {
-            Target::Incipit
-        } else if let Some(service) = self.services.iter().find(|&service| service.host == *host) {
-            Target::Socket((self.addr(), service.port).into())
-        } else {
-            Target::Unknown
-        }
+    fn route(&self, host: &str, path: &str) -> Route {
+        RoutingTable::new(self).route(host, path)
     }
 }
 
-impl HostMapping for Arc<RwLock<Config>> {
-    fn route(&self, host: &str) -> Target {
-        // TODO: Handle this with eyre
-        let config = self.read().expect("Lock should not be poisoned");
-        config.route(host)
+impl Config {
+    /// Where requests for `service` go: its `static` files, or its address (see
+    /// [`Config::address`]), spoken to with FastCGI if it has `fastcgi`.
+    pub(super) fn target(&self, service: &ServiceConfig) -> Target {
+        if let Some(files) = &service.static_files {
+            return Target::Static(StaticConfig {
+                dir: self.service_dir(service).join(&files.dir),
+                ..files.clone()
+            });
+        }
+
+        let address = self.address(service);
+
+        match &service.fastcgi {
+            Some(app) if address != Target::Unknown => Target::FastCgi(
+                Box::new(address),
+                FastCgiConfig {
+                    root: self.service_dir(service).join(&app.root),
+                    ..app.clone()
+                },
+            ),
+            _ => address,
+        }
+    }
+
+    /// The address that `service` listens on: its `socket`, its `upstream`, or its `port` on
+    /// [`Config::addr`].
+    fn address(&self, service: &ServiceConfig) -> Target {
+        if let Some(socket) = &service.socket {
+            return Target::Unix(self.root_dir().join(socket));
+        }
+
+        let Some(upstream) = &service.upstream else {
+            return match service.port {
+                Some(port) => Target::Socket((self.addr(), port).into()),
+                None => Target::Unknown,
+            };
+        };
+
+        let Ok((host, Some(port))) = host::split_port(upstream) else {
+            return Target::Unknown;
+        };
+
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        match host.parse::<IpAddr>() {
+            Ok(ip) => Target::Socket((ip, port).into()),
+            Err(_) => Target::Hostname(host.to_string(), port),
+        }
     }
 }
 
+/// Returns the length of `prefix` if `path` is under it (`/grafana` matches `/grafana` and
+/// `/grafana/d/1`, but not `/grafanas`). No prefix matches every path, with a length of 0.
+pub(super) fn prefix_len(prefix: Option<&str>, path: &str) -> Option<usize> {
+    let Some(prefix) = prefix else {
+        return Some(0);
+    };
+
+    let rest = path.strip_prefix(prefix)?;
+    (rest.is_empty() || rest.starts_with('/')).then_some(prefix.len())
+}
+
 impl<T> HostMapping for T
 where
-    T: Fn(&str) -> Target,
+    T: Fn(&str, &str) -> Target,
 {
-    fn route(&self, host: &str) -> Target {
-        self(host)
+    fn route(&self, host: &str, path: &str) -> Route {
+        self(host, path).into()
     }
 }
-
-// impl<K, V, S> HostMapping for std::collections::HashMap<K, V, S>
-// where
-//     K: for<'k> From<&'k String> + std::cmp::Eq + std::hash::Hash,
-//     for<'v> &'v V: Into<SocketAddr>,
-//     S: std::hash::BuildHasher,
-// {
-//     fn route(&self, Host(host): &Host) -> SocketAddr {
-//         self.get(&K::from(host)).map(Into::into)
-//     }
-// }
@@ -1,77 +1,428 @@
 //! Utilities to forward requests from one host to another.
 
+mod error_page;
+mod fastcgi;
+mod files;
+mod maintenance;
 mod mapping;
+mod pool;
+mod provider;
+mod split;
+mod table;
 mod websocket;
 
 #[cfg(test)]
 mod test;
 
 use axum::{
-    extract::{Host, Request, State},
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Uri, Version},
     middleware::Next,
     response::{IntoResponse, Response},
 };
-use color_eyre::eyre;
+use color_eyre::eyre::{self, Context as _};
 use hyper::StatusCode;
-use hyper_util::rt::TokioIo;
-use mapping::Target;
-use std::{
-    net::SocketAddr,
-    sync::{Arc, RwLock},
+use std::{io, sync::Arc};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, UnixStream},
 };
-use tokio::net::TcpStream;
 
-pub use mapping::HostMapping;
+pub use error_page::{ErrorPages, REQUEST_ID_HEADER};
+pub use maintenance::{Maintenance, MaintenanceMode, BYPASS_COOKIE};
+pub use mapping::{HostMapping, Redirect, Route, Target};
+pub use pool::{Pool, PoolSettings};
+pub use provider::{Chain, Registry, RouteProvider, RoutesFile};
+pub use split::Split;
+pub use table::{Routes, RoutingTable};
 
-use crate::Config;
+use crate::config::host;
 
-async fn forward_to_addr(request: Request, addr: SocketAddr) -> eyre::Result<Response> {
-    tracing::trace!("Forwarding request {request:?} to {addr}");
+use error_page::Failure;
 
-    let stream = TcpStream::connect(addr).await?;
-    let io = TokioIo::new(stream);
+/// Header with the part of the host matched by a wildcard host, such as `alice` for
+/// `alice.example.com` with `*.example.com`.
+pub const SUBDOMAIN_HEADER: &str = "x-incipit-subdomain";
 
-    let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await?;
+/// Header with the path prefix of the service, such as `/grafana`.
+pub const PREFIX_HEADER: &str = "x-forwarded-prefix";
 
-    tokio::task::spawn(async move {
-        if let Err(error) = conn.await {
-            tracing::error!("Connection failed: {error}");
+/// A connection to an upstream, over TCP or a unix socket.
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Stream for T {}
+
+/// Opens a connection to `target`, which has to be an upstream.
+async fn connect(target: &Target) -> eyre::Result<Box<dyn Stream>> {
+    let stream: Box<dyn Stream> = match target {
+        Target::Socket(addr) => Box::new(TcpStream::connect(addr).await?),
+        Target::Hostname(host, port) => Box::new(TcpStream::connect((host.as_str(), *port)).await?),
+        Target::Unix(path) => Box::new(
+            UnixStream::connect(path)
+                .await
+                .wrap_err_with(|| format!("Failed to connect to {}", path.display()))?,
+        ),
+        Target::Static(_)
+        | Target::FastCgi(..)
+        | Target::Redirect(_)
+        | Target::Incipit
+        | Target::Unknown => {
+            eyre::bail!("{target:?} is not an upstream")
         }
-    });
+    };
 
-    Ok(sender.send_request(request).await?.into_response())
+    Ok(stream)
 }
 
-async fn forward(request: Request, target: Target, next: Next) -> eyre::Result<Response> {
+async fn forward_to_upstream(
+    mut request: Request,
+    target: &Target,
+    pool: &Arc<Pool>,
+    settings: PoolSettings,
+) -> eyre::Result<Response> {
+    tracing::trace!("Forwarding request {request:?} to {target:?}");
+
+    // Upstreams are spoken to in HTTP/1.1, with the path in origin-form. HTTP/2 requests only
+    // have the host in the URI (as `:authority`), so it has to be moved to the `Host` header.
+    if let Some(authority) = request.uri().authority().cloned() {
+        let host = HeaderValue::from_str(authority.as_str())?;
+        request.headers_mut().entry(header::HOST).or_insert(host);
+    }
+    if let Some(path) = request.uri().path_and_query().cloned() {
+        *request.uri_mut() = path.into();
+    }
+    *request.version_mut() = Version::HTTP_11;
+
+    Ok(pool.send(target, request, settings).await?.into_response())
+}
+
+/// Gets the response to `request` from `target`, giving up on services that don't start
+/// answering within the `upstream_timeout` of `table`.
+async fn forward(
+    request: Request,
+    target: Target,
+    next: Next,
+    table: &RoutingTable,
+    pool: &Arc<Pool>,
+) -> eyre::Result<Response> {
+    let timeout = table.upstream_timeout();
+
     let response = match target {
-        Target::Socket(addr) => forward_to_addr(request, addr).await?,
-        Target::Incipit => next.run(request).await,
-        Target::Unknown => {
-            (StatusCode::NOT_FOUND, "404 - Host not known by incipit").into_response()
+        Target::Socket(_) | Target::Hostname(..) | Target::Unix(_) => {
+            let forwarded = forward_to_upstream(request, &target, pool, table.pool_settings());
+            tokio::time::timeout(timeout, forwarded).await??
+        }
+        Target::Static(files) => files::serve(&request.into_parts().0, &files).await?,
+        Target::FastCgi(address, app) => {
+            tokio::time::timeout(timeout, fastcgi::forward(request, &address, &app)).await??
         }
+        Target::Redirect(redirect) => redirect_response(&request, &redirect),
+        Target::Incipit => next.run(request).await,
+        Target::Unknown => eyre::bail!("Host not known by incipit"),
     };
 
     Ok(response)
 }
 
+/// The status of a request whose `target` failed with `err`: a service that is stopped (its
+/// socket doesn't exist) is unavailable, a service that is too slow is a timeout, and anything
+/// else that goes wrong with a service is a bad gateway.
+fn failure_status(target: &Target, err: &eyre::Report) -> StatusCode {
+    if !(target.is_upstream() || matches!(target, Target::FastCgi(..))) {
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+
+    if err.downcast_ref::<tokio::time::error::Elapsed>().is_some() {
+        return StatusCode::GATEWAY_TIMEOUT;
+    }
+
+    let stopped = err
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|err| err.kind() == io::ErrorKind::NotFound);
+    match stopped {
+        true => StatusCode::SERVICE_UNAVAILABLE,
+        false => StatusCode::BAD_GATEWAY,
+    }
+}
+
+/// Returns the scheme that the client used for `request`.
+fn request_scheme(request: &Request) -> &str {
+    // incipit is usually behind something that terminates TLS, which tells the original scheme.
+    request
+        .headers()
+        .get("x-forwarded-proto")
+        .and_then(|proto| proto.to_str().ok())
+        .or_else(|| request.uri().scheme_str())
+        .unwrap_or("http")
+}
+
+fn redirect_response(request: &Request, redirect: &Redirect) -> Response {
+    let mut location = if redirect.to.starts_with('/') || redirect.to.contains("://") {
+        redirect.to.clone()
+    } else {
+        format!("{}://{}", request_scheme(request), redirect.to)
+    };
+
+    if let (false, Some(query)) = (location.contains('?'), request.uri().query()) {
+        location.push('?');
+        location.push_str(query);
+    }
+
+    (redirect.status, [(header::LOCATION, location)]).into_response()
+}
+
+/// Removes `prefix` from the path of `request`, so `/grafana/d/1` becomes `/d/1` (and `/grafana`
+/// becomes `/`).
+fn strip_path_prefix(request: &mut Request, prefix: &str) -> eyre::Result<()> {
+    let uri = request.uri();
+    let path = uri.path().strip_prefix(prefix).unwrap_or(uri.path());
+    let path = if path.is_empty() { "/" } else { path };
+
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse()?);
+    *request.uri_mut() = Uri::from_parts(parts)?;
+
+    Ok(())
+}
+
+/// The cookies in the `Cookie` headers of a request.
+fn request_cookies(headers: &HeaderMap) -> Vec<(&str, &str)> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| {
+            let (name, value) = cookie.split_once('=')?;
+            Some((name.trim(), value.trim().trim_matches('"')))
+        })
+        .collect()
+}
+
+/// Returns the normalized host of a request (see [`host::normalize_authority`]).
+fn request_host(request: &Request, listener_port: u16) -> Option<String> {
+    let authority = request_authority(request.uri(), request.headers())?;
+    host::normalize_authority(authority, listener_port)
+}
+
+/// Returns the authority (host and port) of a request, as sent by the client.
+///
+/// The authority of the URI comes before the `Host` header, since that's where HTTP/2 puts the
+/// host (as `:authority`) and it takes precedence for absolute-form requests (like
+/// `GET http://example.com/ HTTP/1.1`).
+fn request_authority<'a>(uri: &'a Uri, headers: &'a HeaderMap) -> Option<&'a str> {
+    let authority = match uri.authority() {
+        Some(authority) => authority.as_str(),
+        None => headers.get(header::HOST)?.to_str().ok()?,
+    };
+
+    // Strip the credentials of `user:password@host`.
+    authority.rsplit('@').next()
+}
+
+/// Where the drawbridge middleware gets its routes from.
+pub struct Drawbridge {
+    /// Routes of the config, which also has the options that apply to every request (such as
+    /// `redirect_to_https`).
+    routes: Arc<Routes>,
+
+    /// Providers asked for hosts that aren't in the config.
+    fallbacks: Chain,
+
+    /// Services put in or out of maintenance from the dashboard.
+    maintenance: Arc<MaintenanceMode>,
+
+    /// Connections to upstreams, which outlive reloads of the config.
+    pool: Arc<Pool>,
+}
+
+impl Drawbridge {
+    pub fn new(routes: Arc<Routes>, fallbacks: Chain) -> Self {
+        Self {
+            routes,
+            fallbacks,
+            maintenance: Arc::default(),
+            pool: Arc::default(),
+        }
+    }
+
+    /// The maintenance mode of the services, which can be changed while incipit runs.
+    pub fn maintenance(&self) -> Arc<MaintenanceMode> {
+        Arc::clone(&self.maintenance)
+    }
+}
+
+impl RouteProvider for Drawbridge {
+    fn lookup<'a>(
+        &'a self,
+        host: &'a str,
+        path: &'a str,
+    ) -> futures::future::BoxFuture<'a, Option<Route>> {
+        Box::pin(async move {
+            match self.routes.lookup(host, path).await {
+                Some(route) => Some(route),
+                None => self.fallbacks.lookup(host, path).await,
+            }
+        })
+    }
+}
+
 /// Middleware to forward requests to the appropriate target.
 pub async fn middleware(
-    State(config): State<Arc<RwLock<Config>>>,
-    Host(host): Host,
-    request: Request,
+    State(drawbridge): State<Arc<Drawbridge>>,
+    mut request: Request,
     next: Next,
 ) -> Response {
-    let target = config.read().unwrap().route(&host);
+    let table = drawbridge.routes.load();
+    let host = request_host(&request, table.listener_port());
+    let path = request.uri().path().to_string();
+
+    let Route {
+        target,
+        subdomain,
+        prefix,
+        strip_prefix,
+        split,
+        error_pages,
+        maintenance,
+    } = match &host {
+        Some(host) if table.redirect_to_https() && request_scheme(&request) != "https" => Some(
+            Target::Redirect(Redirect {
+                to: format!("https://{host}{path}"),
+                status: StatusCode::PERMANENT_REDIRECT,
+            })
+            .into(),
+        ),
+        // The table that was just loaded, rather than `drawbridge.lookup`, which would load it
+        // again and could get a newer one.
+        Some(host) => match table.lookup(host, &path).await {
+            Some(route) => Some(route),
+            None => drawbridge.fallbacks.lookup(host, &path).await,
+        },
+        None => None,
+    }
+    // Requests for hosts that no provider knows (or without a host) go to the default service.
+    .or_else(|| table.default_route(&path))
+    .unwrap_or_default();
+
+    let error_pages = error_pages.unwrap_or_else(|| table.error_pages());
+    let accept = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let failure = Failure {
+        host: host.unwrap_or_default(),
+        path,
+        request_id: Failure::request_id(request.headers()),
+        message: None,
+    };
+
+    if target == Target::Unknown {
+        return error_pages
+            .render(StatusCode::NOT_FOUND, &accept, &failure)
+            .await;
+    }
+
+    // Never pass along a subdomain sent by the client, services should be able to trust it.
+    request.headers_mut().remove(SUBDOMAIN_HEADER);
+    if let Some(value) = subdomain.and_then(|s| HeaderValue::from_str(&s).ok()) {
+        request.headers_mut().insert(SUBDOMAIN_HEADER, value);
+    }
+
+    // Likewise for the prefix, which services use to build their URLs.
+    request.headers_mut().remove(PREFIX_HEADER);
+    if let Some(prefix) = prefix {
+        if strip_prefix {
+            if let Err(err) = strip_path_prefix(&mut request, &prefix) {
+                tracing::warn!(
+                    "Request {} to {}{} has an invalid path: {err:#}",
+                    failure.request_id,
+                    failure.host,
+                    failure.path,
+                );
+                let failure = Failure {
+                    message: Some("The path of the request is invalid"),
+                    ..failure
+                };
+                return error_pages
+                    .render(StatusCode::BAD_REQUEST, &accept, &failure)
+                    .await;
+            }
+        }
+
+        if let Ok(value) = HeaderValue::from_str(&prefix) {
+            request.headers_mut().insert(PREFIX_HEADER, value);
+        }
+    }
+
+    if let Some(maintenance) = maintenance {
+        if drawbridge.maintenance.blocks(&maintenance, &request) {
+            let failure = Failure {
+                message: Some("The service is under maintenance"),
+                ..failure
+            };
+            let mut response = error_pages
+                .render(StatusCode::SERVICE_UNAVAILABLE, &accept, &failure)
+                .await;
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, maintenance.retry_after().into());
+            return response;
+        }
+    }
+    maintenance::strip_bypass_cookie(request.headers_mut());
+
+    let (target, sticky_cookie) = match split {
+        Some(split) => split.choose(&request, target),
+        None => (target, None),
+    };
 
     let (parts, body) = request.into_parts();
     let mut request = Request::from_parts(parts.clone(), body);
 
-    if let Some(response) = websocket::handle(&mut request, parts, target).await {
-        return response;
+    let websocket = match target.is_upstream() {
+        true => websocket::handle(&mut request, parts, target.clone()).await,
+        false => None,
+    };
+
+    let mut response = match websocket {
+        Some(response) => response,
+        None => match forward(request, target.clone(), next, &table, &drawbridge.pool).await {
+            Ok(response) => response,
+            Err(err) => {
+                let status = failure_status(&target, &err);
+                tracing::warn!(
+                    "Request {} to {}{} failed with {status}: {err:#}",
+                    failure.request_id,
+                    failure.host,
+                    failure.path,
+                );
+                error_pages.render(status, &accept, &failure).await
+            }
+        },
+    };
+
+    if response.extensions().get::<files::NotFound>().is_some() {
+        let failure = Failure {
+            message: Some("File not found"),
+            ..failure
+        };
+        response = error_pages
+            .render(StatusCode::NOT_FOUND, &accept, &failure)
+            .await;
     }
 
-    match forward(request, target, next).await {
-        Ok(response) => response,
-        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, format!("500 - {err}")).into_response(),
+    if let Some(cookie) = sticky_cookie {
+        response.headers_mut().append(header::SET_COOKIE, cookie);
     }
+
+    response
 }
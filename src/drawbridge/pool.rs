@@ -0,0 +1,385 @@
+//! Connections to upstreams, kept open between requests so that most requests don't have to
+//! connect and do a handshake first.
+//!
+//! A connection goes back to the pool once the response to its request has been read, and is
+//! closed after being idle for a while (or when there are already enough idle connections to its
+//! upstream). Expired connections are closed in the background, even when no request comes.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{body::Body, extract::Request};
+use color_eyre::eyre;
+use hyper::{body::Incoming, client::conn::http1::SendRequest, Response};
+use hyper_util::rt::TokioIo;
+
+use super::{connect, Target};
+
+/// How many idle connections are kept for each upstream, unless `upstream_max_idle` is set.
+const DEFAULT_MAX_IDLE: usize = 32;
+
+/// How long idle connections are kept, unless `upstream_idle_timeout` is set.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// How often expired and closed connections are looked for.
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many connections are kept, and for how long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolSettings {
+    /// How many idle connections are kept for each upstream. With 0, connections are never
+    /// reused.
+    pub max_idle: usize,
+    pub idle_timeout: Duration,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        Self {
+            max_idle: DEFAULT_MAX_IDLE,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+        }
+    }
+}
+
+/// A connection waiting for a request.
+struct Idle {
+    sender: SendRequest<Body>,
+    expires: Instant,
+}
+
+impl Idle {
+    fn is_usable(&self) -> bool {
+        Instant::now() < self.expires && !self.sender.is_closed()
+    }
+}
+
+/// Idle connections to upstreams, by upstream.
+#[derive(Default)]
+pub struct Pool {
+    idle: Mutex<HashMap<Target, Vec<Idle>>>,
+    /// Whether the task that closes expired connections has been started.
+    reaping: AtomicBool,
+}
+
+impl Pool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends `request` to `target` (which has to be an upstream), on an idle connection if there
+    /// is one.
+    pub async fn send(
+        self: &Arc<Self>,
+        target: &Target,
+        mut request: Request,
+        settings: PoolSettings,
+    ) -> eyre::Result<Response<Incoming>> {
+        loop {
+            let (mut sender, reused) = match self.checkout(target) {
+                Some(sender) => (sender, true),
+                None => (handshake(target).await?, false),
+            };
+
+            match sender.try_send_request(request).await {
+                Ok(response) => {
+                    self.release(target, sender, settings);
+                    return Ok(response);
+                }
+                Err(mut err) => match err.take_message() {
+                    // The upstream closed the connection while it was idle, before the request
+                    // could be sent, so it can be sent again on another one.
+                    Some(unsent) if reused => request = unsent,
+                    _ => return Err(err.into_error().into()),
+                },
+            }
+        }
+    }
+
+    /// Takes an idle connection to `target`, closing the ones that expired on the way.
+    fn checkout(&self, target: &Target) -> Option<SendRequest<Body>> {
+        let mut idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+        let connections = idle.get_mut(target)?;
+
+        // The most recently used connections are the least likely to have been closed.
+        while let Some(connection) = connections.pop() {
+            if connection.is_usable() && connection.sender.is_ready() {
+                return Some(connection.sender);
+            }
+        }
+
+        None
+    }
+
+    /// Puts `sender` back in the pool once the response to its request has been read, if the
+    /// connection is still open then.
+    fn release(
+        self: &Arc<Self>,
+        target: &Target,
+        mut sender: SendRequest<Body>,
+        settings: PoolSettings,
+    ) {
+        if settings.max_idle == 0 {
+            return;
+        }
+
+        if !self.reaping.swap(true, Ordering::Relaxed) {
+            tokio::spawn(reap(Arc::downgrade(self)));
+        }
+
+        let pool = Arc::clone(self);
+        let target = target.clone();
+        tokio::spawn(async move {
+            if sender.ready().await.is_ok() {
+                pool.checkin(target, sender, settings);
+            }
+        });
+    }
+
+    fn checkin(&self, target: Target, sender: SendRequest<Body>, settings: PoolSettings) {
+        let mut idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let connections = idle.entry(target).or_default();
+        connections.retain(Idle::is_usable);
+        if connections.len() < settings.max_idle {
+            connections.push(Idle {
+                sender,
+                expires: Instant::now() + settings.idle_timeout,
+            });
+        }
+    }
+
+    /// Closes the connections that expired or that the upstream closed.
+    fn prune(&self) {
+        // Dropping the senders closes the connections.
+        self.idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|_, connections| {
+                connections.retain(Idle::is_usable);
+                !connections.is_empty()
+            });
+    }
+
+    /// How many idle connections there are to `target`.
+    #[cfg(test)]
+    fn idle(&self, target: &Target) -> usize {
+        self.idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(target)
+            .map_or(0, Vec::len)
+    }
+}
+
+/// Prunes `pool` every [`REAP_INTERVAL`], until it is dropped.
+async fn reap(pool: Weak<Pool>) {
+    let mut interval = tokio::time::interval(REAP_INTERVAL);
+    loop {
+        interval.tick().await;
+        match pool.upgrade() {
+            Some(pool) => pool.prune(),
+            None => break,
+        }
+    }
+}
+
+/// Opens an HTTP/1.1 connection to `target`.
+async fn handshake(target: &Target) -> eyre::Result<SendRequest<Body>> {
+    let io = TokioIo::new(connect(target).await?);
+    let (sender, conn) = hyper::client::conn::http1::handshake(io).await?;
+
+    tokio::task::spawn(async move {
+        if let Err(error) = conn.await {
+            tracing::error!("Connection failed: {error}");
+        }
+    });
+
+    Ok(sender)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use hyper::{server::conn::http1, service::service_fn};
+    use tokio::{
+        io::{AsyncReadExt as _, AsyncWriteExt as _},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Starts an upstream that counts its connections, closing them after each response unless
+    /// `keep_alive`.
+    async fn upstream(keep_alive: bool) -> eyre::Result<(Target, Arc<AtomicUsize>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let target = Target::Socket(listener.local_addr()?);
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&connections);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let service = service_fn(|_| async {
+                    eyre::Ok(Response::new(http_body_util::Full::new(&b"Hello"[..])))
+                });
+                tokio::spawn(
+                    http1::Builder::new()
+                        .keep_alive(keep_alive)
+                        .serve_connection(TokioIo::new(stream), service),
+                );
+            }
+        });
+
+        Ok((target, connections))
+    }
+
+    async fn get(pool: &Arc<Pool>, target: &Target, settings: PoolSettings) -> eyre::Result<()> {
+        let request = Request::builder()
+            .uri("/")
+            .header("host", "example.com")
+            .body(Body::empty())?;
+        let response = pool.send(target, request, settings).await?;
+
+        let body = http_body_util::BodyExt::collect(response.into_body()).await?;
+        assert_eq!(body.to_bytes(), "Hello");
+
+        // Let the connection go back to the pool.
+        tokio::task::yield_now().await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reuses_connections() -> eyre::Result<()> {
+        let pool = Arc::new(Pool::new());
+        let settings = PoolSettings::default();
+
+        let (target, connections) = upstream(true).await?;
+        for _ in 0..5 {
+            get(&pool, &target, settings).await?;
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.idle(&target), 1);
+
+        // Connections that the upstream closes are never reused.
+        let (target, connections) = upstream(false).await?;
+        for _ in 0..3 {
+            get(&pool, &target, settings).await?;
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+        assert_eq!(pool.idle(&target), 0);
+
+        // Nor are the ones that the upstream closes without telling.
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let target = Target::Socket(listener.local_addr()?);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&connections);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).await;
+                let _ = stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nHello")
+                    .await;
+            }
+        });
+        for _ in 0..3 {
+            get(&pool, &target, settings).await?;
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+        assert_eq!(pool.idle(&target), 0);
+
+        // Nor are the ones that were idle for too long.
+        let (target, connections) = upstream(true).await?;
+        let settings = PoolSettings {
+            idle_timeout: Duration::ZERO,
+            ..settings
+        };
+        for _ in 0..3 {
+            get(&pool, &target, settings).await?;
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn closes_expired_connections() -> eyre::Result<()> {
+        let pool = Arc::new(Pool::new());
+        let settings = PoolSettings {
+            idle_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+
+        let (target, _) = upstream(true).await?;
+        get(&pool, &target, settings).await?;
+        assert_eq!(pool.idle(&target), 1);
+
+        // Without any other request.
+        tokio::time::sleep(REAP_INTERVAL + Duration::from_millis(200)).await;
+        assert_eq!(pool.idle(&target), 0);
+
+        Ok(())
+    }
+
+    /// Sends `count` requests at the same time.
+    async fn get_concurrently(
+        pool: &Arc<Pool>,
+        target: &Target,
+        settings: PoolSettings,
+        count: usize,
+    ) -> eyre::Result<()> {
+        let requests: Vec<_> = (0..count)
+            .map(|_| {
+                let (pool, target) = (Arc::clone(pool), target.clone());
+                tokio::spawn(async move { get(&pool, &target, settings).await })
+            })
+            .collect();
+        for request in requests {
+            request.await??;
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handles_concurrent_requests() -> eyre::Result<()> {
+        let pool = Arc::new(Pool::new());
+        let settings = PoolSettings {
+            max_idle: 8,
+            ..Default::default()
+        };
+        let (target, connections) = upstream(true).await?;
+
+        // Each connection only has one request at a time, so there is one for each request.
+        get_concurrently(&pool, &target, settings, settings.max_idle).await?;
+        let opened = connections.load(Ordering::SeqCst);
+        assert!(opened <= settings.max_idle, "{opened} connections");
+        assert_eq!(pool.idle(&target), opened);
+
+        // Which are enough for as many requests at a time, and for requests one at a time.
+        for _ in 0..10 {
+            get_concurrently(&pool, &target, settings, settings.max_idle).await?;
+        }
+        for _ in 0..10 {
+            get(&pool, &target, settings).await?;
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), opened);
+
+        // With more requests at a time, more connections are opened, but only some are kept.
+        get_concurrently(&pool, &target, settings, 100).await?;
+        assert!(connections.load(Ordering::SeqCst) > opened);
+        assert!(pool.idle(&target) <= settings.max_idle);
+
+        Ok(())
+    }
+}
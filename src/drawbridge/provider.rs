@@ -0,0 +1,267 @@
+//! Sources of routes, which can be stacked so that a host unknown to one of them is looked up in
+//! the next.
+//!
+//! incipit itself routes with the config (see [`super::Routes`]), and programs embedding it can
+//! add their own providers after it with [`crate::run_with`]: services registered at runtime
+//! ([`Registry`]), a file of hosts and targets ([`RoutesFile`]), or anything else that implements
+//! [`RouteProvider`] (such as a lookup in DNS-SD).
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use arc_swap::ArcSwap;
+use color_eyre::eyre::{self, Context as _};
+use futures::future::{self, BoxFuture};
+
+use crate::{
+    config::{host, ServiceConfig},
+    Config,
+};
+
+use super::{mapping::Target, HostMapping, Route, RoutingTable};
+
+/// Something that knows where requests for some hosts go.
+///
+/// Unlike [`HostMapping`], looking up a route can be asynchronous, and returns `None` for hosts that the
+/// provider doesn't know (so that the next provider in a [`Chain`] is asked). Every
+/// [`HostMapping`] is a provider that knows every host that it doesn't route to
+/// [`Target::Unknown`].
+///
+/// The host is normalized (see [`host::normalize`]).
+pub trait RouteProvider: Send + Sync {
+    fn lookup<'a>(&'a self, host: &'a str, path: &'a str) -> BoxFuture<'a, Option<Route>>;
+}
+
+impl<T> RouteProvider for T
+where
+    T: HostMapping + Send + Sync,
+{
+    fn lookup<'a>(&'a self, host: &'a str, path: &'a str) -> BoxFuture<'a, Option<Route>> {
+        let route = HostMapping::route(self, host, path);
+        Box::pin(future::ready(
+            (route.target != Target::Unknown).then_some(route),
+        ))
+    }
+}
+
+/// Providers that are asked in order, until one of them knows the host.
+#[derive(Clone, Default)]
+pub struct Chain(Vec<Arc<dyn RouteProvider>>);
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a provider, which is asked after the ones that are already in the chain.
+    pub fn then(mut self, provider: impl RouteProvider + 'static) -> Self {
+        self.0.push(Arc::new(provider));
+        self
+    }
+
+    /// Like [`Chain::then`], for providers that are shared with something else (such as a
+    /// [`Registry`] that services are added to).
+    pub fn then_shared(mut self, provider: Arc<dyn RouteProvider>) -> Self {
+        self.0.push(provider);
+        self
+    }
+}
+
+impl RouteProvider for Chain {
+    fn lookup<'a>(&'a self, host: &'a str, path: &'a str) -> BoxFuture<'a, Option<Route>> {
+        Box::pin(async move {
+            for provider in &self.0 {
+                if let Some(route) = provider.lookup(host, path).await {
+                    return Some(route);
+                }
+            }
+
+            None
+        })
+    }
+}
+
+/// Hosts that are registered and removed while incipit runs, such as services that announce
+/// themselves through an API.
+///
+/// Only exact hosts are supported, and they are routed to the same target for every path.
+#[derive(Debug, Default)]
+pub struct Registry(ArcSwap<HashMap<String, Target>>);
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Routes requests for `host` to `target`, replacing what it was routed to before.
+    pub fn register(&self, host: &str, target: Target) -> eyre::Result<()> {
+        let host = host::normalize(host)?;
+        self.0.rcu(|hosts| {
+            let mut hosts = HashMap::clone(hosts);
+            hosts.insert(host.clone(), target.clone());
+            hosts
+        });
+
+        Ok(())
+    }
+
+    /// Stops routing requests for `host`. Returns whether it was registered.
+    pub fn unregister(&self, host: &str) -> bool {
+        let Ok(host) = host::normalize(host) else {
+            return false;
+        };
+
+        let previous = self.0.rcu(|hosts| {
+            let mut hosts = HashMap::clone(hosts);
+            hosts.remove(&host);
+            hosts
+        });
+
+        previous.contains_key(&host)
+    }
+}
+
+impl HostMapping for Registry {
+    fn route(&self, host: &str, _path: &str) -> Route {
+        self.0.load().get(host).cloned().unwrap_or_default().into()
+    }
+}
+
+/// Routes from a file with a host and where its requests go on each line, which can be a port, a
+/// `host:port` or a unix socket (as `unix:/path/to/socket`):
+///
+/// ```text
+/// # Comments and empty lines are ignored.
+/// wiki.example.com   8080
+/// *.nas.example.com  192.168.1.20:5000
+/// app.example.com    unix:/run/app.sock
+/// ```
+///
+/// Hosts can be wildcards, like in the config. The file is read when the provider is created and
+/// on [`RoutesFile::reload`].
+#[derive(Debug)]
+pub struct RoutesFile {
+    path: PathBuf,
+    table: ArcSwap<RoutingTable>,
+}
+
+impl RoutesFile {
+    pub fn load(path: impl Into<PathBuf>) -> eyre::Result<Self> {
+        let path = path.into();
+        let table = ArcSwap::from_pointee(Self::read(&path)?);
+
+        Ok(Self { path, table })
+    }
+
+    /// Reads the file again. If it is invalid, the routes that were read before are kept.
+    pub fn reload(&self) -> eyre::Result<()> {
+        self.table.store(Arc::new(Self::read(&self.path)?));
+        Ok(())
+    }
+
+    fn read(path: &Path) -> eyre::Result<RoutingTable> {
+        let content = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read routes from {}", path.display()))?;
+
+        let services = content
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i, line.split('#').next().unwrap_or_default().trim()))
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(i, line)| {
+                Self::parse_line(line)
+                    .wrap_err_with(|| format!("In line {} of {}", i + 1, path.display()))
+            })
+            .collect::<eyre::Result<_>>()?;
+
+        let config = Config {
+            file_path: Some(path.to_path_buf()),
+            services,
+            ..Default::default()
+        };
+
+        Ok(RoutingTable::new(&config))
+    }
+
+    fn parse_line(line: &str) -> eyre::Result<ServiceConfig> {
+        let mut words = line.split_whitespace();
+        let (Some(pattern), Some(target), None) = (words.next(), words.next(), words.next()) else {
+            eyre::bail!("Expected a host and a target, got `{line}`");
+        };
+
+        let pattern = host::normalize(pattern)?;
+        host::validate(&pattern)?;
+
+        let mut service = ServiceConfig {
+            name: pattern.clone(),
+            host: pattern.into(),
+            ..Default::default()
+        };
+
+        if let Some(socket) = target.strip_prefix("unix:") {
+            service.socket = Some(socket.into());
+        } else if let Ok(port) = target.parse() {
+            service.port = Some(port);
+        } else if matches!(host::split_port(target), Ok((host, Some(_))) if !host.is_empty()) {
+            service.upstream = Some(target.to_string());
+        } else {
+            eyre::bail!("Invalid target `{target}`, expected a port, `host:port` or `unix:<path>`");
+        }
+
+        Ok(service)
+    }
+}
+
+impl HostMapping for RoutesFile {
+    fn route(&self, host: &str, path: &str) -> Route {
+        self.table.load().route(host, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn chains_providers() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("routes");
+        std::fs::write(
+            &path,
+            "# Routes\n\nwiki.example.com 8080\n*.nas.example.com nas.lan:5000 # NAS\n",
+        )?;
+
+        let registry = Arc::new(Registry::new());
+        registry.register("Wiki.example.com", Target::port(9090))?;
+        registry.register("app.example.com", Target::port(3000))?;
+
+        let chain = Chain::new()
+            .then(RoutesFile::load(&path)?)
+            .then_shared(registry.clone());
+
+        let target = |host| {
+            let chain = chain.clone();
+            async move { chain.lookup(host, "/").await.map(|route| route.target) }
+        };
+
+        // The file comes first.
+        assert_eq!(target("wiki.example.com").await, Some(Target::port(8080)));
+        assert_eq!(target("app.example.com").await, Some(Target::port(3000)));
+        assert_eq!(
+            target("alice.nas.example.com").await,
+            Some(Target::Hostname("nas.lan".into(), 5000))
+        );
+        assert_eq!(target("unknown.example.com").await, None);
+
+        assert!(registry.unregister("app.example.com"));
+        assert_eq!(target("app.example.com").await, None);
+
+        std::fs::write(&path, "wiki.example.com nowhere\n")?;
+        assert!(RoutesFile::load(&path).is_err());
+
+        Ok(())
+    }
+}
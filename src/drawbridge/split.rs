@@ -0,0 +1,267 @@
+//! Splitting the requests of a service between it and its variants (see
+//! [`crate::config::VariantConfig`]).
+
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::Request,
+    http::{HeaderMap, HeaderName, HeaderValue},
+};
+use percent_encoding::percent_decode_str;
+use rand::Rng as _;
+
+use crate::config::{Config, ServiceConfig};
+
+use super::{request_cookies, Target};
+
+/// How long clients stay on the variant they were sent to, in seconds (30 days).
+const STICKY_MAX_AGE: u32 = 30 * 24 * 60 * 60;
+
+/// The variants of a service, and how to choose between them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Split {
+    /// The name of the service, which is what the cookie stores for the service itself.
+    service: String,
+    /// Name of the cookie that keeps clients on a variant, if the service is sticky.
+    cookie: Option<String>,
+    variants: Vec<Variant>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Variant {
+    name: String,
+    target: Target,
+    weight: u8,
+    headers: Vec<(HeaderName, String)>,
+    cookies: BTreeMap<String, String>,
+    query: BTreeMap<String, String>,
+}
+
+impl Variant {
+    fn matches(
+        &self,
+        headers: &HeaderMap,
+        cookies: &[(&str, &str)],
+        query: &[(String, String)],
+    ) -> bool {
+        let has_rules =
+            !(self.headers.is_empty() && self.cookies.is_empty() && self.query.is_empty());
+
+        has_rules
+            && self
+                .headers
+                .iter()
+                .all(|(name, value)| headers.get_all(name).iter().any(|v| v == value.as_str()))
+            && self
+                .cookies
+                .iter()
+                .all(|(name, value)| cookies.contains(&(name.as_str(), value.as_str())))
+            && self
+                .query
+                .iter()
+                .all(|(name, value)| query.iter().any(|(n, v)| n == name && v == value))
+    }
+}
+
+impl Split {
+    /// The split of `service`, if it has variants.
+    pub fn new(config: &Config, service: &ServiceConfig) -> Option<Self> {
+        if service.variants.is_empty() {
+            return None;
+        }
+
+        let variants = service
+            .variants
+            .iter()
+            .map(|variant| {
+                let instance = ServiceConfig {
+                    port: variant.port,
+                    upstream: variant.upstream.clone(),
+                    socket: variant.socket.clone(),
+                    ..service.clone()
+                };
+
+                Variant {
+                    name: variant.name.clone(),
+                    target: config.target(&instance),
+                    weight: variant.weight,
+                    headers: variant
+                        .headers
+                        .iter()
+                        .filter_map(|(name, value)| Some((name.parse().ok()?, value.clone())))
+                        .collect(),
+                    cookies: variant.cookies.clone(),
+                    query: variant.query.clone(),
+                }
+            })
+            .collect();
+
+        let cookie = service.sticky.then(|| {
+            let name: String = service
+                .name
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+                .collect();
+            format!("incipit-variant-{name}")
+        });
+
+        Some(Self {
+            service: service.name.clone(),
+            cookie,
+            variants,
+        })
+    }
+
+    /// Chooses where `request` goes, `service` being the target of the service itself.
+    ///
+    /// Also returns the cookie to set on the response when a sticky service chose by weight.
+    pub fn choose(&self, request: &Request, service: Target) -> (Target, Option<HeaderValue>) {
+        let cookies = request_cookies(request.headers());
+        let query = query(request.uri().query().unwrap_or_default());
+
+        if let Some(variant) = self
+            .variants
+            .iter()
+            .find(|variant| variant.matches(request.headers(), &cookies, &query))
+        {
+            return (variant.target.clone(), None);
+        }
+
+        let Some(cookie) = &self.cookie else {
+            return (
+                self.pick(rand::thread_rng().gen_range(0..100), service),
+                None,
+            );
+        };
+
+        let assigned = cookies
+            .iter()
+            .find(|(name, _)| name == cookie)
+            .map(|(_, value)| *value);
+        match assigned {
+            Some(name) if name == self.service => return (service, None),
+            Some(name) => {
+                if let Some(variant) = self.variants.iter().find(|v| v.name == name) {
+                    return (variant.target.clone(), None);
+                }
+            }
+            None => {}
+        }
+
+        let roll = rand::thread_rng().gen_range(0..100);
+        let name = self
+            .variant(roll)
+            .map_or(self.service.as_str(), |variant| &variant.name);
+        let set_cookie = HeaderValue::from_str(&format!(
+            "{cookie}={name}; Path=/; Max-Age={STICKY_MAX_AGE}; HttpOnly; SameSite=Lax"
+        ))
+        .ok();
+
+        (self.pick(roll, service), set_cookie)
+    }
+
+    /// The variant that a roll between 0 and 99 lands on, if it doesn't land on the service.
+    fn variant(&self, roll: u8) -> Option<&Variant> {
+        let mut total = 0;
+        self.variants.iter().find(|variant| {
+            total += u16::from(variant.weight);
+            u16::from(roll) < total
+        })
+    }
+
+    fn pick(&self, roll: u8, service: Target) -> Target {
+        self.variant(roll)
+            .map_or(service, |variant| variant.target.clone())
+    }
+}
+
+/// The decoded parameters of a query string.
+fn query(query: &str) -> Vec<(String, String)> {
+    let decode = |s: &str| {
+        percent_decode_str(&s.replace('+', " "))
+            .decode_utf8_lossy()
+            .into_owned()
+    };
+
+    query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            (decode(name), decode(value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::VariantConfig;
+
+    use super::*;
+
+    #[test]
+    fn chooses_variants() {
+        let config = Config::default();
+        let service = ServiceConfig {
+            name: "app".into(),
+            port: Some(3000),
+            sticky: true,
+            variants: vec![
+                VariantConfig {
+                    name: "beta".into(),
+                    port: Some(3001),
+                    weight: 30,
+                    headers: [("X-Beta".into(), "1".into())].into(),
+                    ..Default::default()
+                },
+                VariantConfig {
+                    name: "next".into(),
+                    port: Some(3002),
+                    weight: 20,
+                    query: [("version".into(), "next one".into())].into(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let split = Split::new(&config, &service).unwrap();
+        let target = |port| Target::Socket((config.addr(), port).into());
+
+        let choose = |headers: &[(&str, &str)], uri: &str| {
+            let mut request = Request::builder().uri(uri);
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            split.choose(&request.body(Default::default()).unwrap(), target(3000))
+        };
+
+        assert_eq!(choose(&[("x-beta", "1")], "/"), (target(3001), None));
+        assert_eq!(choose(&[], "/?a&version=next+one"), (target(3002), None));
+        assert_eq!(
+            choose(&[("cookie", "a=1; incipit-variant-app=next")], "/"),
+            (target(3002), None)
+        );
+        assert_eq!(
+            choose(&[("cookie", "incipit-variant-app=app")], "/"),
+            (target(3000), None)
+        );
+
+        // Without a rule or a cookie, the weights decide and the choice is remembered.
+        let (chosen, cookie) = choose(&[("cookie", "incipit-variant-app=gone")], "/");
+        let cookie = cookie.unwrap();
+        let name = cookie.to_str().unwrap().split([';', '=']).nth(1).unwrap();
+        let port = match name {
+            "app" => 3000,
+            "beta" => 3001,
+            "next" => 3002,
+            _ => panic!("unexpected variant `{name}`"),
+        };
+        assert_eq!(chosen, target(port));
+
+        assert_eq!(split.variant(0).unwrap().name, "beta");
+        assert_eq!(split.variant(29).unwrap().name, "beta");
+        assert_eq!(split.variant(30).unwrap().name, "next");
+        assert_eq!(split.variant(49).unwrap().name, "next");
+        assert!(split.variant(50).is_none());
+    }
+}
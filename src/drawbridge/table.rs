@@ -0,0 +1,415 @@
+//! The routing table, which is compiled from the config so that routing a request doesn't need to
+//! go through every service.
+//!
+//! Exact hosts are looked up in a hash map, and wildcard hosts in a trie of their labels from
+//! right to left (so `*.apps.example.com` is under `com`, `example`, `apps`). Looking up a host
+//! only walks down its own labels, however many services there are.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use arc_swap::ArcSwap;
+use axum::http::StatusCode;
+
+use crate::config::{Config, RedirectConfig};
+
+use super::{
+    mapping::{prefix_len, Target},
+    ErrorPages, HostMapping, Maintenance, PoolSettings, Redirect, Route, Split,
+};
+
+/// How long services have to start answering a request, unless `upstream_timeout` is set.
+const DEFAULT_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Values indexed by host pattern (see [`crate::config::host`]).
+#[derive(Debug)]
+struct HostIndex<T> {
+    exact: HashMap<String, Vec<T>>,
+    wildcards: Node<T>,
+}
+
+/// A node of the wildcard trie, for the suffix made of the labels on the way to it.
+#[derive(Debug)]
+struct Node<T> {
+    children: HashMap<String, Node<T>>,
+    /// Values of `*.<suffix>`.
+    single: Vec<T>,
+    /// Values of `**.<suffix>`.
+    multi: Vec<T>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Self {
+            children: HashMap::new(),
+            single: Vec::new(),
+            multi: Vec::new(),
+        }
+    }
+}
+
+impl<T> Default for HostIndex<T> {
+    fn default() -> Self {
+        Self {
+            exact: HashMap::new(),
+            wildcards: Node::default(),
+        }
+    }
+}
+
+/// Values whose pattern matched a host, along with the part of the host matched by the wildcard.
+type Matched<'a, T> = (&'a [T], Option<&'a str>);
+
+impl<T> HostIndex<T> {
+    fn insert(&mut self, pattern: &str, value: T) {
+        let (single, suffix) = match pattern.strip_prefix("**.") {
+            Some(suffix) => (false, suffix),
+            None => match pattern.strip_prefix("*.") {
+                Some(suffix) => (true, suffix),
+                None => {
+                    self.exact
+                        .entry(pattern.to_string())
+                        .or_default()
+                        .push(value);
+                    return;
+                }
+            },
+        };
+
+        let node = suffix.rsplit('.').fold(&mut self.wildcards, |node, label| {
+            node.children.entry(label.to_string()).or_default()
+        });
+
+        if single {
+            node.single.push(value);
+        } else {
+            node.multi.push(value);
+        }
+    }
+
+    /// Returns the values whose pattern matches `host`, grouped by pattern, from the most specific
+    /// pattern to the least: an exact host, then longer suffixes, then `*` before `**`.
+    fn lookup<'a>(&'a self, host: &'a str) -> Vec<Matched<'a, T>> {
+        let mut matched = Vec::new();
+
+        if let Some(values) = self.exact.get(host) {
+            matched.push((values.as_slice(), None));
+        }
+
+        if host.split('.').any(str::is_empty) {
+            return matched;
+        }
+
+        // The nodes whose suffix `host` ends with, along with how long the suffix is. A node
+        // whose suffix is the whole host doesn't count, since wildcards match at least one label.
+        let mut nodes = Vec::new();
+        let mut node = &self.wildcards;
+        let mut suffix_len = 0;
+        for label in host.rsplit('.') {
+            nodes.push((node, suffix_len));
+
+            match node.children.get(label) {
+                Some(child) => node = child,
+                None => break,
+            }
+            // The label and the dot before it.
+            suffix_len += label.len() + 1;
+        }
+
+        for (node, suffix_len) in nodes.into_iter().rev() {
+            let subdomain = &host[..host.len() - suffix_len];
+
+            if !node.single.is_empty() && !subdomain.contains('.') {
+                matched.push((node.single.as_slice(), Some(subdomain)));
+            }
+            if !node.multi.is_empty() {
+                matched.push((node.multi.as_slice(), Some(subdomain)));
+            }
+        }
+
+        matched
+    }
+}
+
+/// How to route the requests that go to a service.
+#[derive(Debug)]
+struct ServiceRoute {
+    target: Target,
+    prefix: Option<String>,
+    strip_prefix: bool,
+    split: Option<Arc<Split>>,
+    error_pages: Option<Arc<ErrorPages>>,
+    maintenance: Arc<Maintenance>,
+    /// The primary host to redirect aliases to, if the service redirects them.
+    redirect_aliases_to: Option<String>,
+}
+
+impl ServiceRoute {
+    fn route(&self, subdomain: Option<&str>) -> Route {
+        Route {
+            target: self.target.clone(),
+            subdomain: subdomain.map(str::to_string),
+            prefix: self.prefix.clone(),
+            strip_prefix: self.strip_prefix,
+            split: self.split.clone(),
+            error_pages: self.error_pages.clone(),
+            maintenance: Some(Arc::clone(&self.maintenance)),
+        }
+    }
+}
+
+/// A host of a service.
+#[derive(Debug, Clone, Copy)]
+struct ServiceHost {
+    service: usize,
+    alias: bool,
+}
+
+/// Everything needed to route requests, compiled from a [`Config`].
+///
+/// It never changes once built: reloading the config builds a new one (see [`Routes`]).
+#[derive(Debug)]
+pub struct RoutingTable {
+    incipit_host: Option<String>,
+    redirect_to_https: bool,
+    listener_port: u16,
+    upstream_timeout: Duration,
+    pool_settings: PoolSettings,
+    error_pages: Arc<ErrorPages>,
+
+    redirects: Vec<RedirectConfig>,
+    redirect_hosts: HostIndex<usize>,
+
+    services: Vec<ServiceRoute>,
+    service_hosts: HostIndex<ServiceHost>,
+    default_service: Option<usize>,
+}
+
+impl RoutingTable {
+    pub fn new(config: &Config) -> Self {
+        let mut redirect_hosts = HostIndex::default();
+        for (i, redirect) in config.redirects.iter().enumerate() {
+            for host in redirect.host.iter() {
+                redirect_hosts.insert(host, i);
+            }
+        }
+
+        let mut services = Vec::new();
+        let mut service_hosts = HostIndex::default();
+        for (i, service) in config.services.iter().enumerate() {
+            services.push(ServiceRoute {
+                target: config.target(service),
+                prefix: service.path.clone(),
+                strip_prefix: service.strip_path,
+                split: Split::new(config, service).map(Arc::new),
+                error_pages: (!service.error_pages.is_empty())
+                    .then(|| Arc::new(ErrorPages::new(config, Some(service)))),
+                maintenance: Arc::new(Maintenance::new(service)),
+                redirect_aliases_to: service
+                    .redirect_aliases
+                    .then(|| service.host.primary().to_string()),
+            });
+
+            for (j, host) in service.host.iter().enumerate() {
+                let value = ServiceHost {
+                    service: i,
+                    alias: j > 0,
+                };
+                service_hosts.insert(host, value);
+            }
+        }
+
+        Self {
+            incipit_host: config.incipit_host.clone(),
+            redirect_to_https: config.redirect_to_https,
+            listener_port: config.socket().port(),
+            upstream_timeout: config
+                .upstream_timeout
+                .map_or(DEFAULT_UPSTREAM_TIMEOUT, Duration::from_secs),
+            pool_settings: PoolSettings {
+                max_idle: config
+                    .upstream_max_idle
+                    .unwrap_or(PoolSettings::default().max_idle),
+                idle_timeout: config
+                    .upstream_idle_timeout
+                    .map_or(PoolSettings::default().idle_timeout, Duration::from_secs),
+            },
+            error_pages: Arc::new(ErrorPages::new(config, None)),
+            redirects: config.redirects.clone(),
+            redirect_hosts,
+            default_service: config
+                .default_service
+                .as_ref()
+                .and_then(|name| config.services.iter().position(|s| s.name == *name)),
+            services,
+            service_hosts,
+        }
+    }
+
+    /// The port that incipit listens on, which isn't part of the host of requests.
+    pub fn listener_port(&self) -> u16 {
+        self.listener_port
+    }
+
+    /// Whether requests that didn't come through HTTPS are redirected to it.
+    pub fn redirect_to_https(&self) -> bool {
+        self.redirect_to_https
+    }
+
+    /// How long services have to start answering a request.
+    pub fn upstream_timeout(&self) -> Duration {
+        self.upstream_timeout
+    }
+
+    /// Where requests for `path` on a host that nothing else knows go, if there is a default
+    /// service (and `path` is under its prefix).
+    ///
+    /// This isn't part of [`HostMapping::route`], so that the other route providers are asked
+    /// before falling back to the default service.
+    pub fn default_route(&self, path: &str) -> Option<Route> {
+        let service = &self.services[self.default_service?];
+        prefix_len(service.prefix.as_deref(), path)?;
+
+        Some(service.route(None))
+    }
+
+    /// How many connections to upstreams are kept open between requests, and for how long.
+    pub fn pool_settings(&self) -> PoolSettings {
+        self.pool_settings
+    }
+
+    /// The global error pages.
+    pub fn error_pages(&self) -> Arc<ErrorPages> {
+        Arc::clone(&self.error_pages)
+    }
+}
+
+impl HostMapping for RoutingTable {
+    fn route(&self, host: &str, path: &str) -> Route {
+        if self.incipit_host.as_deref() == Some(host) {
+            return Target::Incipit.into();
+        }
+
+        // Redirects are checked in the order of the config.
+        let mut redirects: Vec<usize> = self
+            .redirect_hosts
+            .lookup(host)
+            .into_iter()
+            .flat_map(|(redirects, _)| redirects.iter().copied())
+            .collect();
+        redirects.sort_unstable();
+        redirects.dedup();
+
+        for redirect in redirects.into_iter().map(|i| &self.redirects[i]) {
+            if let Some(to) = redirect.location(path) {
+                return Target::Redirect(Redirect {
+                    to,
+                    status: StatusCode::from_u16(redirect.status)
+                        .unwrap_or(StatusCode::MOVED_PERMANENTLY),
+                })
+                .into();
+            }
+        }
+
+        // The most specific host wins, and then the longest path prefix.
+        for (hosts, subdomain) in self.service_hosts.lookup(host) {
+            let best = hosts
+                .iter()
+                .filter_map(|host| {
+                    let service = &self.services[host.service];
+                    Some((host, service, prefix_len(service.prefix.as_deref(), path)?))
+                })
+                .max_by_key(|(_, _, len)| *len);
+
+            let Some((host, service, _)) = best else {
+                continue;
+            };
+
+            return match &service.redirect_aliases_to {
+                Some(primary) if host.alias => Target::Redirect(Redirect {
+                    to: format!("{primary}{path}"),
+                    status: StatusCode::MOVED_PERMANENTLY,
+                })
+                .into(),
+                _ => service.route(subdomain),
+            };
+        }
+
+        Target::Unknown.into()
+    }
+}
+
+/// The current [`RoutingTable`], which can be replaced while requests are being routed.
+///
+/// Routing only loads a pointer, so it never waits for a reload (and a reload never waits for
+/// requests).
+#[derive(Debug)]
+pub struct Routes(ArcSwap<RoutingTable>);
+
+impl Routes {
+    pub fn new(config: &Config) -> Self {
+        Self(ArcSwap::from_pointee(RoutingTable::new(config)))
+    }
+
+    /// The current routing table. Requests should be routed with a single table, since the
+    /// table can change at any moment.
+    pub fn load(&self) -> Arc<RoutingTable> {
+        self.0.load_full()
+    }
+
+    /// Replaces the routing table with one for `config`.
+    pub fn update(&self, config: &Config) {
+        self.0.store(Arc::new(RoutingTable::new(config)));
+    }
+}
+
+impl HostMapping for Routes {
+    fn route(&self, host: &str, path: &str) -> Route {
+        self.0.load().route(host, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_hosts() {
+        let mut index = HostIndex::default();
+        for (i, pattern) in [
+            "**.example.com",
+            "*.apps.example.com",
+            "**.apps.example.com",
+            "admin.apps.example.com",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            index.insert(pattern, i);
+        }
+
+        let lookup = |host| -> Vec<(Vec<usize>, Option<&str>)> {
+            index
+                .lookup(host)
+                .into_iter()
+                .map(|(values, subdomain)| (values.to_vec(), subdomain))
+                .collect()
+        };
+
+        assert_eq!(
+            lookup("admin.apps.example.com"),
+            [
+                (vec![3], None),
+                (vec![1], Some("admin")),
+                (vec![2], Some("admin")),
+                (vec![0], Some("admin.apps")),
+            ]
+        );
+        assert_eq!(
+            lookup("a.b.apps.example.com"),
+            [(vec![2], Some("a.b")), (vec![0], Some("a.b.apps"))]
+        );
+        assert_eq!(lookup("example.com"), []);
+        assert_eq!(lookup("example.org"), []);
+        assert_eq!(lookup("a..example.com"), []);
+    }
+}
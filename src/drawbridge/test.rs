@@ -2,23 +2,37 @@ use std::time::Duration;
 
 use color_eyre::eyre;
 use futures::{SinkExt as _, TryStreamExt as _};
+use hyper::StatusCode;
 use rand::{distributions::WeightedIndex, prelude::Distribution as _, SeedableRng as _};
 use reqwest_websocket::{Message, RequestBuilderExt as _};
 use serial_test::serial;
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpStream, UnixListener},
+};
 
-use crate::util::{
-    self,
-    test::{WebSocketServer, TEST_INCIPIT_PORT},
+use crate::{
+    config::{
+        ErrorPageConfig, FastCgiConfig, MaintenanceConfig, PathPattern, RedirectConfig,
+        ServiceConfig, StaticConfig, VariantConfig,
+    },
+    util::{
+        self,
+        test::{Server, WebSocketServer, TEST_INCIPIT_PORT},
+    },
 };
 
-use super::{mapping::Target, HostMapping};
+use super::{
+    mapping::Target, Chain, HostMapping, Redirect, Registry, PREFIX_HEADER, SUBDOMAIN_HEADER,
+};
 
-fn example_mapping() -> impl Fn(&str) -> Target {
-    |host| {
+fn example_mapping() -> impl Fn(&str, &str) -> Target {
+    |host, _path| {
         util::test::services()
             .into_iter()
-            .find(|service| service.config.host == host)
-            .map(|service| Target::port(service.config.port))
+            .find(|service| service.config.host.iter().any(|h| h == host))
+            .and_then(|service| service.config.port)
+            .map(Target::port)
             .unwrap_or(Target::Unknown)
     }
 }
@@ -29,11 +43,82 @@ fn mapping_from_config() {
     let mapping = example_mapping();
 
     for service in util::test::services() {
-        let host = &service.config.host;
-        assert_eq!(mapping.route(host), config.route(host));
+        let host = service.config.host.primary();
+        assert_eq!(
+            mapping.route(host, "/").target,
+            config.route(host, "/").target
+        );
     }
 }
 
+#[test]
+fn wildcard_hosts_prefer_most_specific() {
+    let service = |name: &str, port, host: &str| ServiceConfig {
+        name: name.into(),
+        port: Some(port),
+        host: host.into(),
+        ..Default::default()
+    };
+
+    let config = crate::Config {
+        services: vec![
+            service("apps", 1, "**.example.com"),
+            service("tenants", 2, "*.apps.example.com"),
+            service("deep-tenants", 3, "**.apps.example.com"),
+            service("admin", 4, "admin.apps.example.com"),
+        ],
+        ..Default::default()
+    };
+
+    let route = |host| {
+        let route = config.route(host, "/");
+        (route.target, route.subdomain)
+    };
+
+    assert_eq!(route("admin.apps.example.com"), (Target::port(4), None));
+    assert_eq!(
+        route("alice.apps.example.com"),
+        (Target::port(2), Some("alice".into()))
+    );
+    assert_eq!(
+        route("docs.alice.apps.example.com"),
+        (Target::port(3), Some("docs.alice".into()))
+    );
+    assert_eq!(
+        route("blog.example.com"),
+        (Target::port(1), Some("blog".into()))
+    );
+    assert_eq!(route("example.com"), (Target::Unknown, None));
+}
+
+#[test]
+fn paths_prefer_longest_prefix() {
+    let service = |name: &str, port, path: Option<&str>| ServiceConfig {
+        name: name.into(),
+        port: Some(port),
+        host: "example.com".into(),
+        path: path.map(Into::into),
+        ..Default::default()
+    };
+
+    let config = crate::Config {
+        services: vec![
+            service("landing", 1, None),
+            service("api", 2, Some("/api")),
+            service("api-v2", 3, Some("/api/v2")),
+        ],
+        ..Default::default()
+    };
+
+    let target = |path| config.route("example.com", path).target;
+
+    assert_eq!(target("/"), Target::port(1));
+    assert_eq!(target("/apis"), Target::port(1));
+    assert_eq!(target("/api"), Target::port(2));
+    assert_eq!(target("/api/v1/users"), Target::port(2));
+    assert_eq!(target("/api/v2/users"), Target::port(3));
+}
+
 #[tokio::test]
 #[serial]
 async fn forward_http_request_to_correct_server() -> eyre::Result<()> {
@@ -107,6 +192,43 @@ async fn forward_http_request_preserves_other_data() -> eyre::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+#[serial]
+async fn forward_http_request_with_unnormalized_host() -> eyre::Result<()> {
+    let (services, _) = util::test::scaffold().await?;
+
+    for host in [
+        "Service0.Example.COM",
+        "service0.example.com.",
+        "service0.example.com:80",
+        &format!("service0.example.com:{TEST_INCIPIT_PORT}"),
+    ] {
+        assert_eq!(util::test::fetch(host, "/").await?, "Hello world", "{host}");
+    }
+    assert_eq!(services[0].server.history.lock().unwrap().len(), 4);
+
+    let response = util::test::client::builder("service0.example.com:8080", "/")
+        .send()
+        .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    // In absolute-form, the host of the URI wins over the `Host` header.
+    let mut stream = TcpStream::connect(("127.0.0.1", TEST_INCIPIT_PORT)).await?;
+    stream
+        .write_all(
+            b"GET http://SERVICE1.example.com/absolute HTTP/1.1\r\n\
+              Host: service0.example.com\r\n\
+              Connection: close\r\n\r\n",
+        )
+        .await?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    assert!(response.ends_with("Hello path: /absolute"), "{response}");
+
+    Ok(())
+}
+
 #[tokio::test]
 #[serial]
 async fn handle_a_bunch_of_concurrent_requests() -> eyre::Result<()> {
@@ -125,7 +247,7 @@ async fn handle_a_bunch_of_concurrent_requests() -> eyre::Result<()> {
         let i = dist.sample(&mut rng);
         expected_counts[i] += 1;
 
-        let host = &services[i].config.host;
+        let host = services[i].config.host.primary();
         let _response = util::test::fetch(host, "/").await?;
     }
 
@@ -144,20 +266,733 @@ async fn handle_a_bunch_of_concurrent_requests() -> eyre::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+#[serial]
+async fn incipit_host_serves_dashboard() -> eyre::Result<()> {
+    let (services, _) = util::test::scaffold().await?;
+
+    let response = util::test::fetch("incipit.example.com", "/").await?;
+
+    assert!(response.contains("service0.example.com"));
+    for service in &services {
+        assert_eq!(service.server.history.lock().unwrap().len(), 0);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn wildcard_host_sends_subdomain() -> eyre::Result<()> {
+    let server =
+        Server::start(([127, 0, 0, 1], 7531).into(), |_| Ok("Hello tenant".into())).await?;
+
+    let mut config = util::test::example_config();
+    config.services = vec![ServiceConfig {
+        name: "tenants".into(),
+        port: Some(7531),
+        host: "*.tenants.example.com".into(),
+        ..Default::default()
+    }];
+    util::test::start_incipit_with(config).await?;
+
+    let response = util::test::client::builder("alice.tenants.example.com", "/")
+        .header(SUBDOMAIN_HEADER, "mallory")
+        .header(PREFIX_HEADER, "/admin")
+        .send()
+        .await?
+        .text()
+        .await?;
+    assert_eq!(response, "Hello tenant");
+
+    let (request, _) = server.history.lock().unwrap().pop().unwrap();
+    let subdomains: Vec<_> = request.headers().get_all(SUBDOMAIN_HEADER).iter().collect();
+    assert_eq!(subdomains, ["alice"]);
+    assert!(request.headers().get(PREFIX_HEADER).is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn aliases_redirect_to_primary_host() -> eyre::Result<()> {
+    let mut config = util::test::example_config();
+    config.services = vec![
+        ServiceConfig {
+            name: "git".into(),
+            port: Some(7532),
+            host: vec!["git.example.com".into(), "git.lan".into()].into(),
+            redirect_aliases: true,
+            ..Default::default()
+        },
+        ServiceConfig {
+            name: "wiki".into(),
+            port: Some(7533),
+            host: vec!["wiki.example.com".into(), "wiki.lan".into()].into(),
+            ..Default::default()
+        },
+    ];
+
+    assert_eq!(config.route("wiki.lan", "/").target, Target::port(7533));
+    util::test::start_incipit_with(config).await?;
+
+    let response = util::test::client::builder("git.lan", "/user/repo?tab=issues")
+        .header("X-Forwarded-Proto", "https")
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        response.headers()["location"],
+        "https://git.example.com/user/repo?tab=issues"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn path_prefix_is_stripped() -> eyre::Result<()> {
+    let server = Server::start(([127, 0, 0, 1], 7534).into(), |path| {
+        Ok(format!("Grafana at {path}"))
+    })
+    .await?;
+
+    let mut config = util::test::example_config();
+    config.services = vec![ServiceConfig {
+        name: "grafana".into(),
+        port: Some(7534),
+        host: "example.com".into(),
+        path: Some("/grafana".into()),
+        strip_path: true,
+        ..Default::default()
+    }];
+    util::test::start_incipit_with(config).await?;
+
+    let response = util::test::fetch("example.com", "/grafana/d/1?orgId=1").await?;
+    assert_eq!(response, "Grafana at /d/1");
+
+    let response = util::test::fetch("example.com", "/grafana").await?;
+    assert_eq!(response, "Grafana at /");
+
+    let (request, _) = server.history.lock().unwrap().pop().unwrap();
+    assert_eq!(request.headers()[PREFIX_HEADER], "/grafana");
+
+    let response = util::test::client::builder("example.com", "/grafanas")
+        .send()
+        .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn forward_to_upstream_host_name() -> eyre::Result<()> {
+    let _server = Server::start(([127, 0, 0, 1], 7535).into(), |_| Ok("Hello NAS".into())).await?;
+
+    let mut config = util::test::example_config();
+    config.services = vec![ServiceConfig {
+        name: "nas".into(),
+        port: None,
+        upstream: Some("localhost:7535".into()),
+        host: "nas.example.com".into(),
+        ..Default::default()
+    }];
+
+    assert_eq!(
+        config.route("nas.example.com", "/").target,
+        Target::Hostname("localhost".into(), 7535)
+    );
+    util::test::start_incipit_with(config).await?;
+
+    assert_eq!(
+        util::test::fetch("nas.example.com", "/").await?,
+        "Hello NAS"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn forward_to_unix_socket() -> eyre::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("app.sock");
+    let listener = UnixListener::bind(&path)?;
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await?;
+
+        let mut request = Vec::new();
+        while !request.ends_with(b"\r\n\r\n") {
+            request.push(stream.read_u8().await?);
+        }
+
+        stream
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\nHello unix",
+            )
+            .await?;
+
+        eyre::Ok(String::from_utf8(request)?)
+    });
+
+    let mut config = util::test::example_config();
+    config.services = vec![ServiceConfig {
+        name: "app".into(),
+        port: None,
+        socket: Some(path),
+        host: "app.example.com".into(),
+        ..Default::default()
+    }];
+    util::test::start_incipit_with(config).await?;
+
+    assert_eq!(
+        util::test::fetch("app.example.com", "/hi").await?,
+        "Hello unix"
+    );
+
+    let request = server.await??;
+    assert!(request.starts_with("GET /hi HTTP/1.1\r\n"), "{request}");
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn serve_static_files() -> eyre::Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir(dir.path().join("docs"))?;
+    std::fs::write(dir.path().join("index.html"), "<h1>Home</h1>")?;
+    std::fs::write(dir.path().join("app.js"), "console.log(1)")?;
+    std::fs::write(dir.path().join("app.js.gz"), "gzipped")?;
+    std::fs::write(dir.path().join(".env"), "SECRET=1")?;
+
+    let mut config = util::test::example_config();
+    config.services = vec![ServiceConfig {
+        name: "site".into(),
+        port: None,
+        static_files: Some(StaticConfig {
+            dir: dir.path().to_path_buf(),
+            index: vec!["index.html".into()],
+            spa: true,
+            list_directories: false,
+        }),
+        host: "site.example.com".into(),
+        ..Default::default()
+    }];
+    util::test::start_incipit_with(config).await?;
+
+    let get = |path| util::test::client::builder("site.example.com", path);
+
+    let response = get("/").send().await?;
+    assert_eq!(response.headers()["content-type"], "text/html");
+    let etag = response.headers()["etag"].clone();
+    assert_eq!(response.text().await?, "<h1>Home</h1>");
+
+    let response = get("/").header("If-None-Match", etag).send().await?;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let response = get("/app.js").header("Range", "bytes=8-").send().await?;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()["content-range"], "bytes 8-13/14");
+    assert_eq!(response.text().await?, "log(1)");
+
+    let response = get("/app.js")
+        .header("Accept-Encoding", "gzip")
+        .send()
+        .await?;
+    assert_eq!(response.headers()["content-encoding"], "gzip");
+    assert_eq!(response.headers()["content-type"], "text/javascript");
+
+    // Unknown paths are routed by the single page app, but hidden files aren't served.
+    assert_eq!(
+        get("/settings/profile").send().await?.text().await?,
+        "<h1>Home</h1>"
+    );
+    let response = get("/.env").send().await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.text().await?, "404 - File not found\n");
+
+    let response = get("/docs").send().await?;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.headers()["location"], "docs/");
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn forward_to_fastcgi() -> eyre::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("php-fpm.sock");
+    let listener = UnixListener::bind(&path)?;
+    std::fs::write(dir.path().join("style.css"), "body {}")?;
+    std::fs::write(dir.path().join("index.php"), "<?php")?;
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await?;
+
+        // Everything up to the empty record that ends the body.
+        let mut request = Vec::new();
+        loop {
+            let mut header = [0; 8];
+            stream.read_exact(&mut header).await?;
+            request.extend(header);
+
+            let len = usize::from(u16::from_be_bytes([header[4], header[5]]));
+            let mut content = vec![0; len + usize::from(header[6])];
+            stream.read_exact(&mut content).await?;
+            request.extend(content);
+
+            if header[1] == 5 && len == 0 {
+                break;
+            }
+        }
+
+        let stdout = b"Status: 201 Created\r\nContent-Type: text/plain\r\n\r\nHello PHP";
+        let mut response = vec![1, 6, 0, 1, 0, stdout.len() as u8, 0, 0];
+        response.extend(stdout);
+        response.extend([1, 3, 0, 1, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        stream.write_all(&response).await?;
+
+        eyre::Ok(String::from_utf8_lossy(&request).into_owned())
+    });
+
+    let mut config = util::test::example_config();
+    config.services = vec![ServiceConfig {
+        name: "wiki".into(),
+        port: None,
+        socket: Some(path),
+        fastcgi: Some(FastCgiConfig {
+            root: dir.path().to_path_buf(),
+            index: "index.php".into(),
+            front_controller: None,
+            params: [("PHP_VALUE".into(), "upload_max_filesize=1G".into())].into(),
+            max_body_size: 1024,
+            max_response_size: 1024,
+        }),
+        host: "wiki.example.com".into(),
+        ..Default::default()
+    }];
+    util::test::start_incipit_with(config).await?;
+
+    let response =
+        util::test::client::builder("wiki.example.com", "/index.php/Main_Page?action=edit")
+            .send()
+            .await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(response.text().await?, "Hello PHP");
+
+    let request = server.await??;
+    let script = format!("{}/index.php", dir.path().display());
+    for param in [
+        "SCRIPT_FILENAME",
+        &script,
+        "PATH_INFO/Main_Page",
+        "QUERY_STRINGaction=edit",
+        "HTTP_HOSTwiki.example.com",
+        "REMOTE_ADDR127.0.0.1",
+        "PHP_VALUEupload_max_filesize=1G",
+    ] {
+        assert!(request.contains(param), "{param} not in {request:?}");
+    }
+
+    // Files that aren't scripts are served by incipit.
+    assert_eq!(
+        util::test::fetch("wiki.example.com", "/style.css").await?,
+        "body {}"
+    );
+
+    // Bodies are read before running the script, so there is a limit.
+    let response = util::test::client::builder("wiki.example.com", "/index.php")
+        .body(vec![b'a'; 2048])
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn fall_back_to_other_providers() -> eyre::Result<()> {
+    let services = util::test::start_services().await?;
+
+    let registry = std::sync::Arc::new(Registry::new());
+    registry.register("registered.example.com", Target::port(1234))?;
+
+    let fallbacks = Chain::new()
+        .then(|host: &str, _path: &str| match host {
+            // Already in the config, so this is never asked.
+            "service0.example.com" => Target::port(9423),
+            _ => Target::Unknown,
+        })
+        .then_shared(registry.clone());
+    util::test::start_incipit_with_fallbacks(util::test::example_config(), fallbacks).await?;
+
+    assert_eq!(
+        util::test::fetch("registered.example.com", "/").await?,
+        "Hello world"
+    );
+    assert_eq!(
+        util::test::fetch("service0.example.com", "/").await?,
+        "Hello world"
+    );
+    assert_eq!(services[0].server.history.lock().unwrap().len(), 2);
+
+    registry.unregister("registered.example.com");
+    let response = util::test::client::builder("registered.example.com", "/")
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn split_traffic_between_variants() -> eyre::Result<()> {
+    let _current = Server::start(([127, 0, 0, 1], 7536).into(), |_| Ok("current".into())).await?;
+    let _beta = Server::start(([127, 0, 0, 1], 7537).into(), |_| Ok("beta".into())).await?;
+    let _rewrite = Server::start(([127, 0, 0, 1], 7538).into(), |_| Ok("rewrite".into())).await?;
+
+    let mut config = util::test::example_config();
+    config.services = vec![ServiceConfig {
+        name: "app".into(),
+        port: Some(7536),
+        host: "app.example.com".into(),
+        sticky: true,
+        variants: vec![
+            VariantConfig {
+                name: "beta".into(),
+                port: Some(7537),
+                headers: [("X-Beta".into(), "1".into())].into(),
+                ..Default::default()
+            },
+            VariantConfig {
+                name: "rewrite".into(),
+                port: Some(7538),
+                weight: 100,
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    }];
+    util::test::start_incipit_with(config).await?;
+
+    let fetch = |header: (&'static str, &'static str)| async move {
+        let response = util::test::client::builder("app.example.com", "/")
+            .header(header.0, header.1)
+            .send()
+            .await?;
+        let cookie = response
+            .headers()
+            .get("set-cookie")
+            .map(|value| value.to_str().unwrap().to_string());
+        eyre::Ok((response.text().await?, cookie))
+    };
+
+    assert_eq!(fetch(("X-Beta", "1")).await?, ("beta".into(), None));
+
+    let (body, cookie) = fetch(("X-Beta", "0")).await?;
+    assert_eq!(body, "rewrite");
+    assert!(cookie.unwrap().starts_with("incipit-variant-app=rewrite;"));
+
+    // Clients that were sent to the service itself stay there.
+    let sticky = ("Cookie", "incipit-variant-app=app");
+    assert_eq!(fetch(sticky).await?, ("current".into(), None));
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn failures_get_error_pages() -> eyre::Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(
+        dir.path().join("404.html"),
+        "<h1>No {{host}} here</h1><p>{{request_id}}</p>",
+    )?;
+    std::fs::write(dir.path().join("503.json"), r#"{"down":"{{path}}"}"#)?;
+
+    // Accepts connections, but never answers.
+    let hanging = tokio::net::TcpListener::bind("127.0.0.1:7539").await?;
+
+    let mut config = util::test::example_config();
+    config.file_path = Some(dir.path().join("incipit.toml"));
+    config.upstream_timeout = Some(1);
+    config.error_pages = [(
+        "404".into(),
+        ErrorPageConfig {
+            html: Some("404.html".into()),
+            json: None,
+        },
+    )]
+    .into();
+    config.services = vec![
+        ServiceConfig {
+            name: "stopped".into(),
+            socket: Some(dir.path().join("stopped.sock")),
+            host: "stopped.example.com".into(),
+            error_pages: [(
+                "503".into(),
+                ErrorPageConfig {
+                    html: None,
+                    json: Some("503.json".into()),
+                },
+            )]
+            .into(),
+            ..Default::default()
+        },
+        ServiceConfig {
+            name: "refused".into(),
+            port: Some(7540),
+            host: "refused.example.com".into(),
+            ..Default::default()
+        },
+        ServiceConfig {
+            name: "hanging".into(),
+            port: Some(7539),
+            host: "hanging.example.com".into(),
+            ..Default::default()
+        },
+    ];
+    util::test::start_incipit_with(config).await?;
+
+    let get = |host, accept| async move {
+        let response = util::test::client::builder(host, "/some/path")
+            .header("Accept", accept)
+            .header("X-Request-Id", "abc123")
+            .send()
+            .await?;
+        eyre::Ok((response.status(), response.text().await?))
+    };
+
+    assert_eq!(
+        get("unknown.example.com", "text/html").await?,
+        (
+            StatusCode::NOT_FOUND,
+            "<h1>No unknown.example.com here</h1><p>abc123</p>".into()
+        )
+    );
+    assert_eq!(
+        get("stopped.example.com", "application/json").await?,
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"down":"/some/path"}"#.into()
+        )
+    );
+
+    // Without a page of their own, failures get incipit's page and don't tell what went wrong.
+    let (status, body) = get("refused.example.com", "*/*").await?;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body, "502 - The service couldn't be reached\n");
+
+    let (status, body) = get("hanging.example.com", "application/json").await?;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert!(body.contains(r#""request_id":"abc123""#));
+
+    drop(hanging);
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn default_service_gets_unknown_hosts() -> eyre::Result<()> {
+    let _services = util::test::start_services().await?;
+    let server = Server::start(([127, 0, 0, 1], 7541).into(), |path| {
+        Ok(format!("Landing page at {path}"))
+    })
+    .await?;
+
+    let mut config = util::test::example_config();
+    config.services.push(ServiceConfig {
+        name: "landing".into(),
+        port: Some(7541),
+        host: "example.com".into(),
+        ..Default::default()
+    });
+    config.default_service = Some("landing".into());
+
+    let fallbacks = Chain::new().then(|host: &str, _path: &str| match host {
+        "old.example.com" => Target::Redirect(Redirect {
+            to: "example.com".into(),
+            status: StatusCode::MOVED_PERMANENTLY,
+        }),
+        _ => Target::Unknown,
+    });
+    util::test::start_incipit_with_fallbacks(config, fallbacks).await?;
+
+    assert_eq!(
+        util::test::fetch("unknown.example.com", "/a").await?,
+        "Landing page at /a"
+    );
+    assert_eq!(
+        util::test::fetch("192.168.1.10", "/").await?,
+        "Landing page at /"
+    );
+    assert_eq!(
+        util::test::fetch("service0.example.com", "/").await?,
+        "Hello world"
+    );
+    assert_eq!(server.history.lock().unwrap().len(), 2);
+
+    // Other providers are asked before the default service.
+    let response = util::test::client::builder("old.example.com", "/")
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn maintenance_mode() -> eyre::Result<()> {
+    let _server = Server::start(([127, 0, 0, 1], 7542).into(), |_| Ok("Migrated".into())).await?;
+
+    let mut config = util::test::example_config();
+    config.services = vec![ServiceConfig {
+        name: "app".into(),
+        port: Some(7542),
+        host: "app.example.com".into(),
+        maintenance: Some(MaintenanceConfig {
+            enabled: true,
+            retry_after: 120,
+            bypass_token: Some("s3cret".into()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }];
+    config.dashboard_allow = vec!["127.0.0.1".into(), "::1".into()];
+    util::test::start_incipit_with(config).await?;
+
+    let get = |cookie: &'static str| async move {
+        util::test::client::builder("app.example.com", "/")
+            .header("Cookie", cookie)
+            .send()
+            .await
+    };
+
+    let response = get("").await?;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()["retry-after"], "120");
+    assert_eq!(
+        response.text().await?,
+        "503 - The service is under maintenance\n"
+    );
+
+    let response = get("incipit-maintenance=s3cret").await?;
+    assert_eq!(response.text().await?, "Migrated");
+
+    let set_maintenance = |enabled: &'static str, origin: &'static str| {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .post(format!(
+                "http://localhost:{}/services/app/maintenance",
+                util::test::TEST_INCIPIT_PORT
+            ))
+            .header("Host", "incipit.example.com")
+            .header("Origin", origin)
+            .form(&[("enabled", enabled)])
+            .send()
+    };
+
+    // Other sites can't make visitors of the dashboard change things.
+    let response = set_maintenance("false", "https://evil.example.com").await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(get("").await?.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let response = set_maintenance("false", "http://incipit.example.com").await?;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(get("").await?.text().await?, "Migrated");
+
+    set_maintenance("true", "http://incipit.example.com").await?;
+    assert_eq!(get("").await?.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    Ok(())
+}
+
+#[test]
+fn redirects_before_services() {
+    let mut config = util::test::example_config();
+    config.redirects = vec![
+        RedirectConfig {
+            host: "service0.example.com".into(),
+            path: Some(PathPattern::try_from(r"^/old/(\w+)$".to_string()).unwrap()),
+            to: "/new/$1".into(),
+            status: 307,
+        },
+        RedirectConfig {
+            host: "**.old.example.com".into(),
+            path: None,
+            to: "https://example.com".into(),
+            status: 301,
+        },
+    ];
+
+    let redirect = |to: &str, status| {
+        Target::Redirect(Redirect {
+            to: to.into(),
+            status,
+        })
+    };
+
+    assert_eq!(
+        config.route("service0.example.com", "/old/page").target,
+        redirect("/new/page", StatusCode::TEMPORARY_REDIRECT)
+    );
+    assert_eq!(
+        config.route("service0.example.com", "/old/a/b").target,
+        Target::port(1234)
+    );
+    assert_eq!(
+        config.route("a.b.old.example.com", "/x").target,
+        redirect("https://example.com/x", StatusCode::MOVED_PERMANENTLY)
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn redirect_to_https() -> eyre::Result<()> {
+    let (services, _) = util::test::scaffold_with(|config| config.redirect_to_https = true).await?;
+
+    let response = util::test::client::builder("service0.example.com", "/path?query=1")
+        .send()
+        .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        response.headers()["location"],
+        "https://service0.example.com/path?query=1"
+    );
+
+    let response = util::test::client::builder("service0.example.com", "/")
+        .header("X-Forwarded-Proto", "https")
+        .send()
+        .await?;
+    assert_eq!(response.text().await?, "Hello world");
+    assert_eq!(services[0].server.history.lock().unwrap().len(), 1);
+
+    Ok(())
+}
+
 #[tokio::test]
 #[serial]
 async fn forward_websockets() -> eyre::Result<()> {
     let config = crate::config::ServiceConfig {
-        port: 4455,
-        host: "websockets.example.com".to_string(),
+        port: Some(4455),
+        host: "websockets.example.com".into(),
         name: "websocket_service".to_string(),
-        repo: None,
-        command: None,
+        ..Default::default()
     };
 
     // TODO: This should be a test utility function and yada yada
 
-    let mut server = WebSocketServer::start(([127, 0, 0, 1], config.port).into()).await?;
+    let mut server = WebSocketServer::start(([127, 0, 0, 1], config.port.unwrap()).into()).await?;
 
     util::test::start_incipit_background().await?;
 
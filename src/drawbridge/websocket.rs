@@ -5,7 +5,7 @@ use axum::{
 };
 use color_eyre::eyre;
 use futures::{SinkExt as _, StreamExt as _};
-use tokio_tungstenite::connect_async;
+use tokio_tungstenite::client_async;
 use tungstenite::client::IntoClientRequest;
 
 use super::mapping::Target;
@@ -36,23 +36,29 @@ async fn serve_websocket(
 ) -> eyre::Result<()> {
     let mut websocket_client = websocket.await?;
 
-    let url = match target {
-        Target::Socket(addr) => format!(
-            "ws://{addr}/{path}",
-            path = request_parts
-                .uri
-                .path_and_query()
-                .map(|v| v.as_str().trim_start_matches('/'))
-                .unwrap_or("")
-        ),
+    let authority = match &target {
+        Target::Socket(addr) => addr.to_string(),
+        Target::Hostname(host, port) => format!("{host}:{port}"),
+        // The request goes through the socket, so the host in the URL isn't used.
+        Target::Unix(_) => "localhost".to_string(),
         _ => return Err(eyre::eyre!("Invalid target for websocket")),
     };
 
+    let url = format!(
+        "ws://{authority}/{path}",
+        path = request_parts
+            .uri
+            .path_and_query()
+            .map(|v| v.as_str().trim_start_matches('/'))
+            .unwrap_or("")
+    );
+
     // Add the headers from the original request to the target request.
     let mut target_request = url.into_client_request()?;
     *target_request.headers_mut() = request_parts.headers.clone();
 
-    let (mut websocket_target, _) = connect_async(target_request).await?;
+    let stream = super::connect(&target).await?;
+    let (mut websocket_target, _) = client_async(target_request, stream).await?;
 
     loop {
         tokio::select! {
@@ -0,0 +1,4 @@
+//! Exporting the config to other tools, as an escape hatch from incipit's own process
+//! supervision.
+
+pub mod systemd;
@@ -0,0 +1,397 @@
+//! Converting services to systemd units, so that systemd supervises the processes and incipit
+//! only reverse-proxies requests to them.
+
+use std::{fmt::Write as _, path::Path};
+
+use color_eyre::eyre;
+
+use crate::config::{Config, RestartPolicy, ServiceConfig};
+
+/// Directory where units usually go.
+pub const UNIT_DIR: &str = "/etc/systemd/system";
+
+/// A systemd unit file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unit {
+    /// File name of the unit, such as `incipit-git.service`.
+    pub name: String,
+
+    pub content: String,
+
+    /// The environment file of the service, if it has environment variables. It goes next to
+    /// the unit, and must only be readable by root, since the values can be secrets (from
+    /// `${env:…}` or `${file:…}`).
+    pub environment: Option<EnvironmentFile>,
+}
+
+/// A file with the environment variables of a service, for `EnvironmentFile=`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvironmentFile {
+    /// File name, such as `incipit-git.env`.
+    pub name: String,
+
+    pub content: String,
+}
+
+/// Name of the unit of a service.
+pub fn unit_name(service: &str) -> String {
+    format!("incipit-{service}.service")
+}
+
+/// Name of the environment file of a service.
+pub fn environment_file_name(service: &str) -> String {
+    format!("incipit-{service}.env")
+}
+
+/// Converts every service with a command into a unit, plus a unit that runs `incipit_exe` as the
+/// proxy. `dir` is the directory that the units (and their environment files) are written to.
+pub fn units(config: &Config, incipit_exe: &Path, dir: &Path) -> eyre::Result<Vec<Unit>> {
+    let mut units = Vec::new();
+
+    for service in &config.services {
+        units.extend(service_unit(config, service, dir)?);
+    }
+
+    units.push(incipit_unit(config, incipit_exe));
+
+    Ok(units)
+}
+
+/// Converts a service into a unit. Returns `None` if the service has no command to run.
+///
+/// Fails if the working directory of the service is relative (which happens when the config
+/// doesn't come from a file), since systemd only accepts absolute ones.
+pub fn service_unit(
+    config: &Config,
+    service: &ServiceConfig,
+    dir: &Path,
+) -> eyre::Result<Option<Unit>> {
+    let Some(command) = &service.command else {
+        return Ok(None);
+    };
+
+    let working_dir = match &service.working_dir {
+        Some(dir) => config.service_dir(service).join(dir),
+        None => config.service_dir(service),
+    };
+
+    eyre::ensure!(
+        working_dir.is_absolute(),
+        "Can't export service `{}`: its working directory `{}` isn't absolute",
+        service.name,
+        working_dir.display()
+    );
+
+    let mut content = String::new();
+
+    content.push_str("[Unit]\n");
+    let _ = writeln!(content, "Description={} (managed by incipit)", service.name);
+
+    // Dependencies without a command have no unit, so they can't be required.
+    let dependencies: Vec<_> = service
+        .depends_on
+        .iter()
+        .filter(|name| {
+            config
+                .services
+                .iter()
+                .any(|s| &s.name == *name && s.command.is_some())
+        })
+        .map(|s| unit_name(s))
+        .collect();
+    let after: Vec<_> = std::iter::once("network.target".to_string())
+        .chain(dependencies.iter().cloned())
+        .collect();
+    let _ = writeln!(content, "After={}", after.join(" "));
+    if !dependencies.is_empty() {
+        let _ = writeln!(content, "Requires={}", dependencies.join(" "));
+    }
+
+    content.push_str("\n[Service]\n");
+    content.push_str("Type=simple\n");
+    let _ = writeln!(
+        content,
+        "ExecStart=/bin/sh -c {}",
+        quote_command(&command.run)
+    );
+    // Unlike most settings, `WorkingDirectory=` doesn't unquote its value.
+    let _ = writeln!(
+        content,
+        "WorkingDirectory={}",
+        working_dir.to_string_lossy().replace('%', "%%")
+    );
+
+    // Anything else would break the environment file, or add other variables to it.
+    if let Some(key) = service.env.keys().find(|key| !is_variable_name(key)) {
+        eyre::bail!(
+            "Can't export service `{}`: `{}` isn't a valid environment variable name",
+            service.name,
+            key.escape_debug()
+        );
+    }
+
+    // The values can be secrets, and units are readable by everyone, so they go in a separate
+    // file.
+    let environment = (!service.env.is_empty()).then(|| {
+        let mut content = String::new();
+        for (key, value) in &service.env {
+            let _ = writeln!(content, "{key}={}", quote_environment(value));
+        }
+
+        EnvironmentFile {
+            name: environment_file_name(&service.name),
+            content,
+        }
+    });
+
+    // Like `WorkingDirectory=`, `EnvironmentFile=` doesn't unquote its value.
+    if let Some(environment) = &environment {
+        let _ = writeln!(
+            content,
+            "EnvironmentFile={}",
+            dir.join(&environment.name)
+                .to_string_lossy()
+                .replace('%', "%%")
+        );
+    }
+
+    let restart = match service.restart {
+        RestartPolicy::Never => "no",
+        RestartPolicy::OnFailure => "on-failure",
+        RestartPolicy::Always => "always",
+    };
+    let _ = writeln!(content, "Restart={restart}");
+
+    content.push_str("\n[Install]\n");
+    content.push_str("WantedBy=multi-user.target\n");
+
+    Ok(Some(Unit {
+        name: unit_name(&service.name),
+        content,
+        environment,
+    }))
+}
+
+/// Unit that runs incipit itself, only to reverse-proxy requests.
+fn incipit_unit(config: &Config, incipit_exe: &Path) -> Unit {
+    let mut content = String::new();
+
+    content.push_str("[Unit]\n");
+    content.push_str("Description=Declarative service manager tailored for home servers\n");
+    content.push_str("After=network.target\n");
+
+    content.push_str("\n[Service]\n");
+    content.push_str("Type=simple\n");
+    let _ = writeln!(
+        content,
+        "ExecStart={}",
+        quote_command(&incipit_exe.to_string_lossy())
+    );
+    if let Some(path) = &config.file_path {
+        let _ = writeln!(
+            content,
+            "Environment={}",
+            quote(&format!("INCIPIT_CONFIG={}", path.display()))
+        );
+    }
+    content.push_str("Restart=on-failure\n");
+
+    content.push_str("\n[Install]\n");
+    content.push_str("WantedBy=multi-user.target\n");
+
+    Unit {
+        name: "incipit.service".to_string(),
+        content,
+        environment: None,
+    }
+}
+
+/// Quotes a value for a unit file, escaping specifiers (`%`).
+fn quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%");
+
+    format!("\"{escaped}\"")
+}
+
+/// Like [`quote`], but also escapes `$` so that systemd doesn't substitute variables in commands
+/// (the shell does it instead).
+fn quote_command(command: &str) -> String {
+    quote(&command.replace('$', "$$"))
+}
+
+/// Whether `name` is a valid name for an environment variable, like `[A-Za-z_][A-Za-z0-9_]*`.
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Quotes a value for an environment file, where only `\`, `"`, `` ` `` and `$` need escaping
+/// inside double quotes.
+fn quote_environment(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '\\' | '"' | '`' | '$') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::config::{CommandConfig, RepoConfig};
+
+    use super::*;
+
+    #[test]
+    fn service_to_unit() {
+        let db = ServiceConfig {
+            name: "db".into(),
+            port: Some(5432),
+            command: Some(CommandConfig {
+                run: "postgres".into(),
+                build: None,
+            }),
+            ..Default::default()
+        };
+        let config = Config {
+            file_path: Some(PathBuf::from("/etc/incipit/incipit.toml")),
+            services: vec![
+                db,
+                ServiceConfig {
+                    name: "nas".into(),
+                    upstream: Some("192.168.1.5:5000".into()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let service = ServiceConfig {
+            name: "app".into(),
+            port: Some(6942),
+            host: "app.example.com".into(),
+            command: Some(CommandConfig {
+                run: "PORT=$PORT node \"build\" # 100%".into(),
+                build: None,
+            }),
+            env: [
+                ("PORT".to_string(), "6942".to_string()),
+                ("TOKEN".to_string(), "s3cr\"t$".to_string()),
+            ]
+            .into(),
+            restart: RestartPolicy::Always,
+            working_dir: Some("100% app".into()),
+            repo: Some(RepoConfig {
+                url: "https://example.com/app.git".into(),
+                branch: None,
+            }),
+            depends_on: vec!["db".into(), "nas".into()],
+            ..Default::default()
+        };
+
+        let unit = service_unit(&config, &service, Path::new("/etc/systemd/system"))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(unit.name, "incipit-app.service");
+        assert_eq!(
+            unit.content,
+            "[Unit]\n\
+             Description=app (managed by incipit)\n\
+             After=network.target incipit-db.service\n\
+             Requires=incipit-db.service\n\
+             \n\
+             [Service]\n\
+             Type=simple\n\
+             ExecStart=/bin/sh -c \"PORT=$$PORT node \\\"build\\\" # 100%%\"\n\
+             WorkingDirectory=/etc/incipit/app/100%% app\n\
+             EnvironmentFile=/etc/systemd/system/incipit-app.env\n\
+             Restart=always\n\
+             \n\
+             [Install]\n\
+             WantedBy=multi-user.target\n"
+        );
+
+        let environment = unit.environment.unwrap();
+        assert_eq!(environment.name, "incipit-app.env");
+        assert_eq!(
+            environment.content,
+            "PORT=\"6942\"\nTOKEN=\"s3cr\\\"t\\$\"\n"
+        );
+    }
+
+    #[test]
+    fn relative_working_dirs_are_refused() {
+        let config = Config {
+            services: vec![ServiceConfig {
+                name: "app".into(),
+                command: Some(CommandConfig {
+                    run: "node build".into(),
+                    build: None,
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let result = units(&config, Path::new("/usr/bin/incipit"), Path::new(UNIT_DIR));
+
+        assert!(result.unwrap_err().to_string().contains("isn't absolute"));
+    }
+
+    #[test]
+    fn invalid_variable_names_are_refused() {
+        let service = |key: &str| ServiceConfig {
+            name: "app".into(),
+            command: Some(CommandConfig {
+                run: "node build".into(),
+                build: None,
+            }),
+            env: [(key.to_string(), "1".to_string())].into(),
+            ..Default::default()
+        };
+        let config = Config {
+            file_path: Some(PathBuf::from("/etc/incipit/incipit.toml")),
+            ..Default::default()
+        };
+        let export = |key| service_unit(&config, &service(key), Path::new(UNIT_DIR));
+
+        assert!(export("_PORT2").is_ok());
+        for key in ["", "2PORT", "A=B", "A B", "A\nB"] {
+            let err = export(key).unwrap_err().to_string();
+            assert!(
+                err.contains("isn't a valid environment variable name"),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn services_without_command_are_skipped() {
+        let config = Config {
+            services: vec![ServiceConfig {
+                name: "nas".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let units = units(&config, Path::new("/usr/bin/incipit"), Path::new(UNIT_DIR)).unwrap();
+
+        assert_eq!(units.len(), 1);
+        assert_eq!(units[0].name, "incipit.service");
+    }
+}
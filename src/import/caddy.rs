@@ -0,0 +1,186 @@
+//! Importing sites from a Caddyfile.
+//!
+//! Only sites with a host that `reverse_proxy` to an upstream are imported, with one service per
+//! site.
+
+use color_eyre::eyre;
+
+use super::{site_host, Imported};
+
+/// A site block of a Caddyfile.
+struct Site {
+    addresses: Vec<String>,
+
+    /// The `reverse_proxy` upstreams of the site, along with whether they get every request
+    /// (instead of only the ones of a `handle` block or a matcher).
+    proxies: Vec<(bool, String)>,
+}
+
+pub fn import(content: &str) -> eyre::Result<Imported> {
+    let mut imported = Imported::default();
+
+    let mut sites: Vec<Site> = Vec::new();
+    let mut depth: i32 = 0;
+    let mut in_site = false;
+
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let opens = line.ends_with('{');
+        let tokens: Vec<&str> = line
+            .trim_end_matches('{')
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|token| !token.is_empty())
+            .collect();
+
+        if depth == 0 && tokens.first().is_some_and(|t| *t != "}") {
+            // Snippets (`(name) { ... }`) and the global options block (`{ ... }`) aren't sites.
+            in_site = !tokens.is_empty() && !tokens[0].starts_with('(');
+
+            if in_site {
+                sites.push(Site {
+                    addresses: tokens.iter().map(|t| t.to_string()).collect(),
+                    proxies: Vec::new(),
+                });
+            }
+        } else if in_site && tokens.first() == Some(&"reverse_proxy") {
+            let mut args = tokens[1..].iter().peekable();
+
+            // Matchers are paths (`/api/*`), named matchers (`@api`) or `*`.
+            let matcher = args.next_if(|t| t.starts_with(['/', '@']) || **t == "*");
+            let whole_site = depth == 1 && matcher.is_none_or(|m| *m == "*");
+
+            if let Some(site) = sites.last_mut() {
+                site.proxies
+                    .extend(args.map(|upstream| (whole_site, upstream.to_string())));
+            }
+        }
+
+        if opens {
+            depth += 1;
+        }
+        depth -= line.matches('}').count() as i32;
+        eyre::ensure!(depth >= 0, "Unbalanced braces in Caddyfile");
+    }
+
+    for mut site in sites {
+        let hosts: Vec<&str> = site
+            .addresses
+            .iter()
+            .map(|a| site_host(a))
+            .filter(|host| !host.is_empty())
+            .collect();
+
+        // Sites like `:8080` match any host, which services can't.
+        let Some(host) = hosts.first() else {
+            imported.warnings.push(format!(
+                "Skipped `{}`: it doesn't have a host",
+                site.addresses.join(", ")
+            ));
+            continue;
+        };
+
+        // Prefer the upstreams that get every request, like nginx's `location /`.
+        site.proxies.sort_by_key(|(whole_site, _)| !whole_site);
+
+        let Some((whole_site, upstream)) = site.proxies.first() else {
+            imported
+                .warnings
+                .push(format!("Skipped `{host}`: it doesn't use `reverse_proxy`"));
+            continue;
+        };
+
+        if !whole_site {
+            imported.warnings.push(format!(
+                "`{host}` only proxies some paths (in a `handle` block or with a matcher) to \
+                 `{upstream}`, but every path was imported"
+            ));
+        }
+
+        if site.proxies.len() > 1 {
+            imported.warnings.push(format!(
+                "`{host}` proxies to different upstreams, only `{upstream}` was kept"
+            ));
+        }
+
+        imported.add_proxied_site(&hosts, upstream);
+    }
+
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_reverse_proxies() -> eyre::Result<()> {
+        let imported = import(
+            r#"
+            {
+                email admin@example.com
+            }
+
+            (common) {
+                encode gzip
+            }
+
+            # Gitea
+            git.example.com {
+                import common
+                reverse_proxy localhost:3000
+            }
+
+            https://wiki.example.com, wiki.lan {
+                handle /api/* {
+                    reverse_proxy /api/* 127.0.0.1:8081
+                }
+            }
+
+            static.example.com {
+                file_server
+            }
+
+            :8080 {
+                reverse_proxy localhost:9000
+            }
+
+            photos.example.com {
+                reverse_proxy /api/* localhost:8081
+                reverse_proxy localhost:8080
+            }
+            "#,
+        )?;
+
+        let services: Vec<_> = imported
+            .services
+            .iter()
+            .map(|s| (s.name.as_str(), s.host.to_string(), s.port))
+            .collect();
+
+        assert_eq!(
+            services,
+            [
+                ("git", "git.example.com".to_string(), Some(3000)),
+                ("wiki", "wiki.example.com, wiki.lan".to_string(), Some(8081)),
+                ("photos", "photos.example.com".to_string(), Some(8080))
+            ]
+        );
+        assert_eq!(
+            imported.warnings,
+            [
+                "`wiki.example.com` only proxies some paths (in a `handle` block or with a \
+                 matcher) to `127.0.0.1:8081`, but every path was imported",
+                "Skipped `static.example.com`: it doesn't use `reverse_proxy`",
+                "Skipped `:8080`: it doesn't have a host",
+                "`photos.example.com` proxies to different upstreams, only `localhost:8080` was \
+                 kept",
+            ]
+        );
+
+        Ok(())
+    }
+}
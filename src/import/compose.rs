@@ -0,0 +1,521 @@
+//! Importing services from a `docker-compose.yml`.
+//!
+//! Each compose service becomes an incipit service that runs its image with `docker run`, on the
+//! first port that it publishes.
+
+use std::{collections::BTreeMap, net::IpAddr};
+
+use color_eyre::eyre::{self, Context as _};
+use serde::Deserialize;
+
+use crate::config::{CommandConfig, HealthCheckConfig, ServiceConfig};
+
+use super::Imported;
+
+#[derive(Debug, Deserialize)]
+struct ComposeFile {
+    #[serde(default)]
+    services: BTreeMap<String, ComposeService>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ComposeService {
+    image: Option<String>,
+    build: Option<serde_yaml::Value>,
+    command: Option<StringOrList>,
+    ports: Vec<Port>,
+    environment: Environment,
+    depends_on: DependsOn,
+    healthcheck: Option<Healthcheck>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum StringOrList {
+    String(String),
+    List(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Port {
+    Long {
+        target: u16,
+        published: Option<serde_yaml::Value>,
+        host_ip: Option<IpAddr>,
+    },
+    Short(serde_yaml::Value),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Environment {
+    Map(BTreeMap<String, Option<serde_yaml::Value>>),
+    List(Vec<String>),
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::List(Vec::new())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum DependsOn {
+    List(Vec<String>),
+    Map(BTreeMap<String, serde_yaml::Value>),
+}
+
+impl Default for DependsOn {
+    fn default() -> Self {
+        Self::List(Vec::new())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Healthcheck {
+    test: Option<StringOrList>,
+    interval: Option<String>,
+}
+
+pub fn import(content: &str, domain: &str) -> eyre::Result<Imported> {
+    let file: ComposeFile =
+        serde_yaml::from_str(content).wrap_err("Failed to parse docker-compose file")?;
+
+    let mut imported = Imported::default();
+
+    for (name, compose) in file.services {
+        let Some(image) = &compose.image else {
+            let reason = match compose.build {
+                Some(_) => "it is built from a Dockerfile, which incipit can't do yet",
+                None => "it has no `image`",
+            };
+            imported
+                .warnings
+                .push(format!("Skipped `{name}`: {reason}"));
+            continue;
+        };
+
+        let ports: Vec<(Option<IpAddr>, u16, u16)> = compose
+            .ports
+            .iter()
+            .filter_map(|port| {
+                let parsed = parse_port(port);
+                if parsed.is_none() {
+                    imported
+                        .warnings
+                        .push(format!("`{name}`: skipped port `{port:?}`"));
+                }
+                parsed
+            })
+            .collect();
+
+        let Some(&(_, published, _)) = ports.first() else {
+            imported
+                .warnings
+                .push(format!("Skipped `{name}`: it doesn't publish any port"));
+            continue;
+        };
+
+        if ports.len() > 1 {
+            imported.warnings.push(format!(
+                "`{name}` publishes several ports, only {published} is proxied to"
+            ));
+        }
+
+        let mut env = environment(compose.environment);
+        for value in env.values_mut() {
+            *value = interpolation(value);
+        }
+
+        let mut run = format!("docker run --rm --name {name}");
+        // Ports that are only published on some address (such as a database on the loopback
+        // one) have to stay that way.
+        for (ip, published, target) in &ports {
+            match ip {
+                Some(IpAddr::V4(ip)) => run.push_str(&format!(" -p {ip}:{published}:{target}")),
+                Some(IpAddr::V6(ip)) => run.push_str(&format!(" -p [{ip}]:{published}:{target}")),
+                None => run.push_str(&format!(" -p {published}:{target}")),
+            }
+        }
+        for var in env.keys() {
+            run.push_str(&format!(" -e {var}"));
+        }
+        run.push_str(&format!(" {}", interpolation(image)));
+        match compose.command {
+            Some(StringOrList::String(command)) => {
+                run.push_str(&format!(" {}", interpolation(&command)))
+            }
+            Some(StringOrList::List(args)) => {
+                for arg in args {
+                    run.push_str(&format!(" {}", shell_quote(&interpolation(&arg))));
+                }
+            }
+            None => {}
+        }
+
+        let depends_on = match compose.depends_on {
+            DependsOn::List(names) => names,
+            DependsOn::Map(names) => names.into_keys().collect(),
+        };
+
+        let health_check = compose
+            .healthcheck
+            .and_then(|check| health_check(&name, check, &mut imported.warnings));
+
+        imported.services.push(ServiceConfig {
+            port: Some(published),
+            host: format!("{name}.{domain}").into(),
+            command: Some(CommandConfig { run, build: None }),
+            env,
+            health_check,
+            depends_on,
+            name,
+            ..Default::default()
+        });
+    }
+
+    // Dependencies on services that weren't imported would make the config invalid.
+    let names: Vec<String> = imported.services.iter().map(|s| s.name.clone()).collect();
+    for service in &mut imported.services {
+        service.depends_on.retain(|dependency| {
+            let known = names.contains(dependency);
+            if !known {
+                imported.warnings.push(format!(
+                    "`{}`: dropped dependency on `{dependency}`, which wasn't imported",
+                    service.name
+                ));
+            }
+            known
+        });
+    }
+
+    Ok(imported)
+}
+
+/// Parses a port mapping into the address it is published on (if it isn't all of them), the
+/// published port and the port in the container.
+///
+/// Mappings can look like `8080:80`, `127.0.0.1:8080:80/tcp`, `[::1]:8080:80` or
+/// `{ target: 80, published: 8080, host_ip: 127.0.0.1 }`. Ranges and ports that aren't published
+/// aren't supported.
+fn parse_port(port: &Port) -> Option<(Option<IpAddr>, u16, u16)> {
+    match port {
+        Port::Short(value) => {
+            let mapping = yaml_to_string(value)?;
+            let mapping = mapping.split('/').next()?;
+
+            let (rest, target) = mapping.rsplit_once(':')?;
+            let (ip, published) = match rest.rsplit_once(':') {
+                Some((ip, published)) => {
+                    let ip = ip.trim_start_matches('[').trim_end_matches(']');
+                    (Some(ip.parse().ok()?), published)
+                }
+                None => (None, rest),
+            };
+
+            Some((ip, published.parse().ok()?, target.parse().ok()?))
+        }
+        Port::Long {
+            target,
+            published,
+            host_ip,
+        } => {
+            let published = yaml_to_string(published.as_ref()?)?.parse().ok()?;
+            Some((*host_ip, published, *target))
+        }
+    }
+}
+
+fn environment(environment: Environment) -> BTreeMap<String, String> {
+    match environment {
+        Environment::Map(vars) => vars
+            .into_iter()
+            .map(|(var, value)| {
+                let value = value.as_ref().and_then(yaml_to_string).unwrap_or_default();
+                (var, value)
+            })
+            .collect(),
+        Environment::List(vars) => vars
+            .into_iter()
+            .map(|var| match var.split_once('=') {
+                Some((var, value)) => (var.to_string(), value.to_string()),
+                None => (var, String::new()),
+            })
+            .collect(),
+    }
+}
+
+/// Converts the variables of a compose value (`$VAR`, `${VAR}` and `$$` for a literal `$`) to
+/// incipit references, so that they are still read from the environment when the config is
+/// loaded (see [`crate::config`]). Anything that incipit doesn't support, such as
+/// `${VAR:-default}`, is kept as it is.
+fn interpolation(value: &str) -> String {
+    let is_name = |name: &str| {
+        name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+
+    let mut output = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            // `$${` would be a literal `${` for incipit too, but a lone `$` doesn't need escaping.
+            output.push_str(if after.starts_with('{') { "$$" } else { "$" });
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('{') {
+            match after.split_once('}') {
+                Some((name, after)) if is_name(name) => {
+                    output.push_str(&format!("${{env:{name}}}"));
+                    rest = after;
+                }
+                _ => {
+                    output.push_str("$${");
+                    rest = after;
+                }
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            match &rest[..end] {
+                name if is_name(name) => output.push_str(&format!("${{env:{name}}}")),
+                name => output.push_str(&format!("${name}")),
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Converts a compose health check into an incipit one, which needs an HTTP path.
+fn health_check(
+    name: &str,
+    check: Healthcheck,
+    warnings: &mut Vec<String>,
+) -> Option<HealthCheckConfig> {
+    let test = match check.test? {
+        StringOrList::String(test) => test,
+        StringOrList::List(args) => args.join(" "),
+    };
+
+    let Some(path) = test
+        .split_whitespace()
+        .find_map(|word| word.trim_matches(['"', '\'']).split_once("://"))
+        .map(|(_, rest)| match rest.find('/') {
+            Some(i) => rest[i..].to_string(),
+            None => "/".to_string(),
+        })
+    else {
+        warnings.push(format!(
+            "`{name}`: skipped health check `{test}`, since it doesn't request a URL"
+        ));
+        return None;
+    };
+
+    let interval = check.interval.as_deref().and_then(|interval| {
+        let seconds = parse_duration(interval);
+        if seconds.is_none() {
+            warnings.push(format!(
+                "`{name}`: ignored health check interval `{interval}`"
+            ));
+        }
+        seconds
+    });
+
+    Some(HealthCheckConfig { path, interval })
+}
+
+/// Parses a compose duration, such as `30s` or `1m30s`, into seconds.
+fn parse_duration(duration: &str) -> Option<u64> {
+    let mut seconds = 0;
+    let mut number = String::new();
+
+    for c in duration.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        seconds += number.parse::<u64>().ok()? * unit;
+        number.clear();
+    }
+
+    if !number.is_empty() {
+        return None;
+    }
+
+    Some(seconds)
+}
+
+fn yaml_to_string(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(s) => Some(s.clone()),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        serde_yaml::Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,".contains(c))
+    {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_compose_services() -> eyre::Result<()> {
+        let imported = import(
+            r#"
+            services:
+              db:
+                image: postgres:16
+                ports: ["127.0.0.1:5432:5432/tcp"]
+                environment:
+                  POSTGRES_PASSWORD: hunter2
+                  POSTGRES_PORT: 5432
+              app:
+                image: ghcr.io/example/app:latest
+                command: ["serve", "--verbose"]
+                ports:
+                  - target: 80
+                    published: "8080"
+                  - "9090:9090"
+                environment:
+                  - DATABASE_URL=postgres://db:5432
+                depends_on:
+                  db:
+                    condition: service_healthy
+                  cache:
+                    condition: service_started
+                healthcheck:
+                  test: ["CMD", "curl", "-f", "http://localhost/health"]
+                  interval: 1m30s
+              builder:
+                build: .
+            "#,
+            "home.lan",
+        )?;
+
+        let app = &imported.services[0];
+        assert_eq!(app.name, "app");
+        assert_eq!(app.port, Some(8080));
+        assert_eq!(app.host.primary(), "app.home.lan");
+        assert_eq!(
+            app.command.as_ref().unwrap().run,
+            "docker run --rm --name app -p 8080:80 -p 9090:9090 -e DATABASE_URL \
+             ghcr.io/example/app:latest serve --verbose"
+        );
+        assert_eq!(app.env["DATABASE_URL"], "postgres://db:5432");
+        assert_eq!(app.depends_on, ["db"]);
+
+        let health_check = app.health_check.as_ref().unwrap();
+        assert_eq!(health_check.path, "/health");
+        assert_eq!(health_check.interval, Some(90));
+
+        let db = &imported.services[1];
+        assert_eq!((db.name.as_str(), db.port), ("db", Some(5432)));
+        assert!(db
+            .command
+            .as_ref()
+            .unwrap()
+            .run
+            .contains(" -p 127.0.0.1:5432:5432 "));
+        assert_eq!(db.env["POSTGRES_PORT"], "5432");
+
+        // Several ports, the unknown `cache` dependency and `builder`.
+        assert_eq!(imported.warnings.len(), 3, "{:?}", imported.warnings);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parses_ports() {
+        let short = |mapping: &str| parse_port(&Port::Short(mapping.into()));
+        let ip = |ip: &str| Some(ip.parse().unwrap());
+
+        assert_eq!(short("8080:80"), Some((None, 8080, 80)));
+        assert_eq!(
+            short("127.0.0.1:5432:5432/tcp"),
+            Some((ip("127.0.0.1"), 5432, 5432))
+        );
+        assert_eq!(short("[::1]:8080:80"), Some((ip("::1"), 8080, 80)));
+        assert_eq!(short("localhost:8080:80"), None);
+        assert_eq!(short("80"), None);
+
+        let long = Port::Long {
+            target: 80,
+            published: Some(8080.into()),
+            host_ip: ip("127.0.0.1"),
+        };
+        assert_eq!(parse_port(&long), Some((ip("127.0.0.1"), 8080, 80)));
+    }
+
+    #[test]
+    fn converts_variables() {
+        assert_eq!(interpolation("${HOME}/data"), "${env:HOME}/data");
+        assert_eq!(interpolation("$USER:$5"), "${env:USER}:$5");
+        assert_eq!(interpolation("$$HOME $${HOME}"), "$HOME $${HOME}");
+        assert_eq!(interpolation("${PORT:-80}"), "$${PORT:-80}");
+    }
+
+    #[test]
+    fn imported_services_load() -> eyre::Result<()> {
+        let imported = import(
+            r#"
+            services:
+              app:
+                image: ghcr.io/example/app:latest
+                command: sh -c 'echo $$PWD; exec app --path "$PATH"'
+                ports: ["8080:80"]
+                environment:
+                  SEARCH_PATH: ${PATH}
+                  GREETING: ${GREETING:-hello}
+            "#,
+            "home.lan",
+        )?;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("incipit.toml");
+        std::fs::write(&path, imported.to_toml()?)?;
+
+        let config = crate::Config::load(&path)?;
+        let app = &config.services[0];
+        let path = std::env::var("PATH")?;
+        assert_eq!(app.env["SEARCH_PATH"], path);
+        assert_eq!(app.env["GREETING"], "${GREETING:-hello}");
+        assert_eq!(
+            app.command.as_ref().unwrap().run,
+            format!(
+                "docker run --rm --name app -p 8080:80 -e GREETING -e SEARCH_PATH \
+                 ghcr.io/example/app:latest sh -c 'echo $PWD; exec app --path \"{path}\"'"
+            )
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+//! Importing services from other tools, to ease migrating an existing setup to incipit.
+
+pub mod caddy;
+pub mod compose;
+pub mod nginx;
+
+use std::{collections::BTreeMap, path::Path};
+
+use color_eyre::eyre;
+
+use crate::config::ServiceConfig;
+
+/// Formats that services can be imported from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// A `docker-compose.yml` file.
+    Compose,
+
+    /// A Caddyfile.
+    Caddy,
+
+    /// An nginx config file, with `server` blocks.
+    Nginx,
+}
+
+impl Format {
+    /// Guesses the format from the name of the file.
+    pub fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+
+        if name.contains("compose") {
+            Some(Self::Compose)
+        } else if name.starts_with("caddyfile") || name.ends_with(".caddyfile") {
+            Some(Self::Caddy)
+        } else if name.ends_with(".conf") || name.contains("nginx") {
+            Some(Self::Nginx)
+        } else {
+            None
+        }
+    }
+}
+
+/// Services converted from another tool.
+#[derive(Debug, Default)]
+pub struct Imported {
+    pub services: Vec<ServiceConfig>,
+
+    /// Things that couldn't be converted exactly, and need to be checked by hand.
+    pub warnings: Vec<String>,
+}
+
+impl Imported {
+    /// Formats the services as an incipit config file (in TOML).
+    pub fn to_toml(&self) -> eyre::Result<String> {
+        #[derive(serde::Serialize)]
+        struct File<'a> {
+            service: BTreeMap<&'a str, &'a ServiceConfig>,
+        }
+
+        let file = File {
+            service: self
+                .services
+                .iter()
+                .map(|service| (service.name.as_str(), service))
+                .collect(),
+        };
+
+        Ok(toml::to_string(&file)?)
+    }
+
+    /// Returns a name for a service that isn't used yet, based on `name`.
+    fn unique_name(&self, name: &str) -> String {
+        let taken = |name: &str| self.services.iter().any(|s| s.name == name);
+
+        if !taken(name) {
+            return name.to_string();
+        }
+
+        (2..)
+            .map(|i| format!("{name}-{i}"))
+            .find(|name| !taken(name))
+            .expect("There are infinitely many names")
+    }
+
+    /// Adds a service for a site that `hosts` reverse-proxy to `upstream`, as found in the
+    /// config of a reverse proxy.
+    fn add_proxied_site(&mut self, hosts: &[&str], upstream: &str) {
+        let host = hosts[0];
+
+        let Some((upstream_host, port)) = parse_upstream(upstream) else {
+            self.warnings.push(format!(
+                "Skipped `{host}`: can't understand upstream `{upstream}`"
+            ));
+            return;
+        };
+
+        // Upstreams on other machines are kept as they are, local ones become just a port.
+        let (port, upstream) = match is_local(&upstream_host) {
+            true => (Some(port), None),
+            false if upstream_host.contains(':') => {
+                (None, Some(format!("[{upstream_host}]:{port}")))
+            }
+            false => (None, Some(format!("{upstream_host}:{port}"))),
+        };
+
+        let label = host.split('.').next().unwrap_or(host);
+        let name = self.unique_name(label);
+
+        self.services.push(ServiceConfig {
+            name,
+            port,
+            upstream,
+            host: hosts
+                .iter()
+                .map(|h| h.to_string())
+                .collect::<Vec<_>>()
+                .into(),
+            ..Default::default()
+        });
+    }
+}
+
+/// Imports the services in `content`. Services that don't have a host in the original config get
+/// `<name>.<domain>`.
+pub fn import(format: Format, content: &str, domain: &str) -> eyre::Result<Imported> {
+    match format {
+        Format::Compose => compose::import(content, domain),
+        Format::Caddy => caddy::import(content),
+        Format::Nginx => nginx::import(content),
+    }
+}
+
+/// Parses the address of an upstream (such as `localhost:3000` or `http://127.0.0.1:3000/`)
+/// into its host and port.
+fn parse_upstream(upstream: &str) -> Option<(String, u16)> {
+    let (default_port, rest) = match upstream.split_once("://") {
+        Some(("https", rest)) => (443, rest),
+        Some((_, rest)) => (80, rest),
+        None => (80, upstream),
+    };
+
+    let authority = rest.split('/').next()?;
+
+    // IPv6 addresses are written in brackets, like `[::1]:3000`.
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.ends_with(']') => (host, port.parse().ok()?),
+        _ => (authority, default_port),
+    };
+
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return None;
+    }
+
+    Some((host.to_string(), port))
+}
+
+fn is_local(host: &str) -> bool {
+    matches!(host, "localhost" | "127.0.0.1" | "::1" | "0.0.0.0")
+}
+
+/// Strips the scheme and port of a site address (such as `https://git.example.com:443`).
+fn site_host(address: &str) -> &str {
+    let address = address.split_once("://").map_or(address, |(_, rest)| rest);
+    let address = address.split('/').next().unwrap_or(address);
+
+    match address.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => address,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upstreams() {
+        let parse = parse_upstream;
+
+        assert_eq!(parse("localhost:3000"), Some(("localhost".into(), 3000)));
+        assert_eq!(
+            parse("http://127.0.0.1:8080/"),
+            Some(("127.0.0.1".into(), 8080))
+        );
+        assert_eq!(parse("https://nas.lan"), Some(("nas.lan".into(), 443)));
+        assert_eq!(parse("[::1]:3000"), Some(("::1".into(), 3000)));
+        assert_eq!(parse("localhost:http"), None);
+    }
+}
@@ -0,0 +1,231 @@
+//! Importing `server` blocks from an nginx config.
+//!
+//! Only servers that `proxy_pass` to an upstream are imported, with one service per server.
+
+use color_eyre::eyre;
+
+use super::{site_host, Imported};
+
+/// A directive of an nginx config, such as `server_name example.com;` or `server { ... }`.
+#[derive(Debug)]
+struct Directive {
+    name: String,
+    args: Vec<String>,
+    block: Option<Vec<Directive>>,
+}
+
+pub fn import(content: &str) -> eyre::Result<Imported> {
+    let directives = parse(&mut tokenize(content).into_iter(), false)?;
+
+    let mut servers = Vec::new();
+    find_servers(&directives, &mut servers);
+
+    let mut imported = Imported::default();
+
+    for server in servers {
+        let hosts: Vec<&str> = server
+            .iter()
+            .filter(|d| d.name == "server_name")
+            .flat_map(|d| &d.args)
+            .map(|host| site_host(host))
+            .filter(|host| *host != "_" && !host.is_empty())
+            .collect();
+
+        let Some(host) = hosts.first() else {
+            imported
+                .warnings
+                .push("Skipped a `server` without `server_name`".to_string());
+            continue;
+        };
+
+        let mut proxies = Vec::new();
+        find_proxy_passes(server, "/", &mut proxies);
+
+        // Prefer the upstream of `location /`, which is the one that gets most requests.
+        proxies.sort_by_key(|(location, _)| *location != "/");
+
+        let Some((_, upstream)) = proxies.first() else {
+            imported
+                .warnings
+                .push(format!("Skipped `{host}`: it doesn't use `proxy_pass`"));
+            continue;
+        };
+
+        if proxies.len() > 1 {
+            imported.warnings.push(format!(
+                "`{host}` proxies different locations to different upstreams, only `{upstream}` \
+                 was kept"
+            ));
+        }
+
+        imported.add_proxied_site(&hosts, upstream);
+    }
+
+    Ok(imported)
+}
+
+fn find_servers<'a>(directives: &'a [Directive], servers: &mut Vec<&'a [Directive]>) {
+    for directive in directives {
+        match (&directive.block, directive.name.as_str()) {
+            (Some(block), "server") => servers.push(block),
+            (Some(block), _) => find_servers(block, servers),
+            (None, _) => {}
+        }
+    }
+}
+
+/// Collects the `proxy_pass` upstreams in `directives`, along with the location they're in.
+fn find_proxy_passes<'a>(
+    directives: &'a [Directive],
+    location: &'a str,
+    proxies: &mut Vec<(&'a str, &'a str)>,
+) {
+    for directive in directives {
+        match (&directive.block, directive.name.as_str()) {
+            (None, "proxy_pass") => {
+                if let Some(upstream) = directive.args.first() {
+                    proxies.push((location, upstream));
+                }
+            }
+            (Some(block), "location") => {
+                let location = directive.args.last().map_or(location, String::as_str);
+                find_proxy_passes(block, location, proxies);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Open,
+    Close,
+    End,
+}
+
+fn tokenize(content: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            ';' => tokens.push(Token::End),
+            '#' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '"' | '\'' => {
+                let mut word = String::new();
+                while let Some(next) = chars.next_if(|&next| next != c) {
+                    word.push(next);
+                }
+                chars.next();
+                tokens.push(Token::Word(word));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = String::from(c);
+                while let Some(next) =
+                    chars.next_if(|&c| !c.is_whitespace() && !matches!(c, '{' | '}' | ';'))
+                {
+                    word.push(next);
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    tokens
+}
+
+fn parse(tokens: &mut impl Iterator<Item = Token>, nested: bool) -> eyre::Result<Vec<Directive>> {
+    let mut directives = Vec::new();
+    let mut words = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Word(word) => words.push(word),
+            Token::End | Token::Open => {
+                let block = match token {
+                    Token::Open => Some(parse(tokens, true)?),
+                    _ => None,
+                };
+
+                let mut words = std::mem::take(&mut words).into_iter();
+                let Some(name) = words.next() else {
+                    eyre::bail!("Expected a directive name");
+                };
+
+                directives.push(Directive {
+                    name,
+                    args: words.collect(),
+                    block,
+                });
+            }
+            Token::Close if nested => return Ok(directives),
+            Token::Close => eyre::bail!("Unexpected `}}` in nginx config"),
+        }
+    }
+
+    eyre::ensure!(!nested, "Missing `}}` in nginx config");
+
+    Ok(directives)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_proxied_servers() -> eyre::Result<()> {
+        let imported = import(
+            r#"
+            http {
+                # Gitea
+                server {
+                    listen 80;
+                    server_name git.example.com gitea.example.com;
+
+                    location / {
+                        proxy_pass http://127.0.0.1:3000;
+                        proxy_set_header Host $host;
+                    }
+                }
+
+                server {
+                    server_name "photos.example.com";
+                    location /api { proxy_pass http://localhost:8081/api; }
+                    location / { proxy_pass http://localhost:8080; }
+                }
+
+                server {
+                    listen 80 default_server;
+                    server_name _;
+                    return 444;
+                }
+            }
+            "#,
+        )?;
+
+        let services: Vec<_> = imported
+            .services
+            .iter()
+            .map(|s| (s.name.as_str(), s.host.to_string(), s.port))
+            .collect();
+
+        assert_eq!(
+            services,
+            [
+                (
+                    "git",
+                    "git.example.com, gitea.example.com".to_string(),
+                    Some(3000)
+                ),
+                ("photos", "photos.example.com".to_string(), Some(8080))
+            ]
+        );
+        assert_eq!(imported.warnings.len(), 2);
+
+        Ok(())
+    }
+}
@@ -1,26 +1,58 @@
 pub mod config;
+pub(crate) mod dashboard;
 pub mod drawbridge;
+pub mod export;
+pub mod import;
 pub(crate) mod util;
 
 pub use config::Config;
 
+use config::ReloadStatus;
+use dashboard::DashboardState;
+use drawbridge::{Chain, Drawbridge, Routes};
+
 use axum::{middleware, Router};
 use color_eyre::eyre::{self, Context as _};
-use std::sync::{Arc, RwLock};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
 use tokio::net::TcpListener;
 
 /// Starts incipit.
 ///
 /// Returns when the server stops.
 pub async fn run(config: Config) -> eyre::Result<()> {
+    run_with(config, Chain::new()).await
+}
+
+/// Starts incipit, asking `fallbacks` for the hosts that aren't in the config.
+///
+/// This is how programs that embed incipit add their own routes (see
+/// [`drawbridge::RouteProvider`]).
+///
+/// Returns when the server stops.
+pub async fn run_with(config: Config, fallbacks: Chain) -> eyre::Result<()> {
     let config = Arc::new(RwLock::new(config));
+    let reload_status = Arc::new(RwLock::new(ReloadStatus::default()));
 
-    let (http_listener, router) = setup(Arc::clone(&config)).await?;
-    let _watcher = config::watch(config)?;
+    let routes = Arc::new(Routes::new(&config.read().unwrap()));
+    let drawbridge = Drawbridge::new(Arc::clone(&routes), fallbacks);
 
-    axum::serve(http_listener, router)
-        .await
-        .wrap_err("Axum server failed")?;
+    let (http_listener, router) = setup(
+        Arc::clone(&config),
+        Arc::clone(&reload_status),
+        Arc::new(drawbridge),
+    )
+    .await?;
+    let _watcher = config::watch(config, reload_status, move |config| routes.update(config))?;
+
+    axum::serve(
+        http_listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .wrap_err("Axum server failed")?;
 
     Ok(())
 }
@@ -28,9 +60,19 @@ pub async fn run(config: Config) -> eyre::Result<()> {
 /// Sets up the server.
 ///
 /// Namely, it binds to the socket specified in the config and sets up the router with the drawbridge middleware.
-pub(crate) async fn setup(config: Arc<RwLock<Config>>) -> eyre::Result<(TcpListener, Router)> {
-    let router = Router::new().layer(middleware::from_fn_with_state(
-        Arc::clone(&config),
+pub(crate) async fn setup(
+    config: Arc<RwLock<Config>>,
+    reload_status: Arc<RwLock<ReloadStatus>>,
+    drawbridge: Arc<Drawbridge>,
+) -> eyre::Result<(TcpListener, Router)> {
+    let dashboard = DashboardState {
+        config: Arc::clone(&config),
+        reload_status,
+        maintenance: drawbridge.maintenance(),
+    };
+
+    let router = dashboard::router(dashboard).layer(middleware::from_fn_with_state(
+        drawbridge,
         drawbridge::middleware,
     ));
 
@@ -1,15 +1,181 @@
-use color_eyre::eyre;
-use incipit::Config;
+use std::path::PathBuf;
+
+use clap::Parser as _;
+use color_eyre::eyre::{self, Context as _};
+use incipit::{export, import, Config};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+/// Declarative service manager tailored for home servers.
+#[derive(clap::Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Start incipit (the default).
+    Run,
+
+    /// Print the JSON Schema of the config file, for editors to autocomplete and validate it.
+    Schema,
+
+    /// Print the config, as read from the config files and the environment.
+    Config {
+        /// Show where each value comes from (file, environment variable or template).
+        #[arg(long)]
+        explain: bool,
+    },
+
+    /// Export the services to other tools.
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat,
+    },
+
+    /// Convert services from a docker-compose file, a Caddyfile or an nginx config, and print
+    /// them as incipit config.
+    Import {
+        /// File to import.
+        file: PathBuf,
+
+        /// Format of the file. If not set, it is guessed from the file name.
+        #[arg(long, value_enum)]
+        format: Option<import::Format>,
+
+        /// Domain for the hosts of services that don't have one (`<service>.<domain>`).
+        #[arg(long, default_value = "example.com")]
+        domain: String,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum ExportFormat {
+    /// Write a systemd unit for each service, plus one for incipit itself (to only proxy).
+    Systemd {
+        /// Directory to write the units to. If not set, they are printed.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
+    let cli = Cli::parse();
+
     setup_tracing_and_eyre()?;
 
-    let config = Config::new()?;
-    tracing::info!("Loaded config {config:#?}");
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => {
+            let config = Config::new()?;
+            tracing::debug!("Loaded config {config:#?}");
+
+            incipit::run(config).await
+        }
+        Command::Schema => {
+            let schema = incipit::config::schema();
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+
+            Ok(())
+        }
+        Command::Config { explain } => {
+            match Config::discover()? {
+                Some(path) => println!("# Config file: {}", path.display()),
+                None => println!("# No config file found"),
+            }
+
+            for value in Config::explain()? {
+                if explain {
+                    println!("{} = {}  # {}", value.key, value.value, value.source);
+                } else {
+                    println!("{} = {}", value.key, value.value);
+                }
+            }
+
+            Ok(())
+        }
+        Command::Export {
+            format: ExportFormat::Systemd { output },
+        } => {
+            let config = Config::new()?;
+            let dir = match &output {
+                Some(dir) => std::path::absolute(dir)?,
+                None => PathBuf::from(export::systemd::UNIT_DIR),
+            };
+            let units = export::systemd::units(&config, &std::env::current_exe()?, &dir)?;
+
+            for unit in units {
+                match &output {
+                    Some(_) => {
+                        let path = dir.join(&unit.name);
+                        std::fs::write(&path, unit.content)
+                            .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+                        tracing::info!("Wrote {}", path.display());
+
+                        if let Some(environment) = unit.environment {
+                            let path = dir.join(&environment.name);
+                            write_private(&path, &environment.content)
+                                .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+                            tracing::info!("Wrote {}", path.display());
+                        }
+                    }
+                    None => {
+                        println!("# {}\n{}", unit.name, unit.content);
+                        if let Some(environment) = unit.environment {
+                            println!("# {}\n{}", environment.name, environment.content);
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        }
+        Command::Import {
+            file,
+            format,
+            domain,
+        } => {
+            let Some(format) = format.or_else(|| import::Format::detect(&file)) else {
+                eyre::bail!(
+                    "Can't tell the format of {}, set it with `--format`",
+                    file.display()
+                );
+            };
+
+            let content = std::fs::read_to_string(&file)
+                .wrap_err_with(|| format!("Failed to read {}", file.display()))?;
+            let imported = import::import(format, &content, &domain)?;
+
+            for warning in &imported.warnings {
+                tracing::warn!("{warning}");
+            }
+
+            print!("{}", imported.to_toml()?);
+
+            Ok(())
+        }
+    }
+}
+
+/// Writes a file that only its owner can read, since it has secrets.
+fn write_private(path: &std::path::Path, content: &str) -> std::io::Result<()> {
+    use std::{
+        fs::Permissions,
+        io::Write as _,
+        os::unix::fs::{OpenOptionsExt as _, PermissionsExt as _},
+    };
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
 
-    incipit::run(config).await
+    // `mode` only applies to new files.
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.write_all(content.as_bytes())
 }
 
 fn setup_tracing_and_eyre() -> eyre::Result<()> {
@@ -18,7 +184,7 @@ fn setup_tracing_and_eyre() -> eyre::Result<()> {
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "incipit=info".into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .init();
 
     color_eyre::install()?;
@@ -1,2 +1,11 @@
 #[cfg(test)]
 pub mod test;
+
+/// Escapes text to put it in HTML, in elements or in quoted attributes.
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -2,13 +2,19 @@ pub mod client;
 mod server;
 mod service;
 
-use std::sync::{Arc, RwLock};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
 
 pub use client::fetch;
 pub use server::{Server, WebSocketServer};
 pub use service::{services, start_services, Service};
 
-use crate::Config;
+use crate::{
+    drawbridge::{Chain, Drawbridge, Routes},
+    Config,
+};
 
 use color_eyre::eyre;
 use tokio::task::JoinHandle;
@@ -23,6 +29,7 @@ pub fn example_config() -> Config {
         port: Some(TEST_INCIPIT_PORT),
         db_path: None,
         services: services().into_iter().map(|s| s.config).collect(),
+        ..Default::default()
     }
 }
 
@@ -30,19 +37,50 @@ pub fn example_config() -> Config {
 ///
 /// Shorthand for [`start_services`] and [`start_incipit_background`].
 pub async fn scaffold() -> eyre::Result<(Vec<Service>, JoinHandle<eyre::Result<()>>)> {
+    scaffold_with(|_| {}).await
+}
+
+/// Like [`scaffold`], but changing [`example_config`] with `edit` first.
+pub async fn scaffold_with(
+    edit: impl FnOnce(&mut Config),
+) -> eyre::Result<(Vec<Service>, JoinHandle<eyre::Result<()>>)> {
+    let mut config = example_config();
+    edit(&mut config);
+
     let services = start_services().await?;
-    let handle = start_incipit_background().await?;
+    let handle = start_incipit_with(config).await?;
     Ok((services, handle))
 }
 
-/// Starts incipit in the background.
+/// Starts incipit in the background, with [`example_config`].
 pub async fn start_incipit_background() -> eyre::Result<JoinHandle<eyre::Result<()>>> {
-    let config = example_config();
+    start_incipit_with(example_config()).await
+}
+
+/// Starts incipit in the background with the given config.
+pub async fn start_incipit_with(config: Config) -> eyre::Result<JoinHandle<eyre::Result<()>>> {
+    start_incipit_with_fallbacks(config, Chain::new()).await
+}
 
-    let (http_listener, router) = crate::setup(Arc::new(RwLock::new(config))).await?;
+/// Like [`start_incipit_with`], asking `fallbacks` for hosts that aren't in the config.
+pub async fn start_incipit_with_fallbacks(
+    config: Config,
+    fallbacks: Chain,
+) -> eyre::Result<JoinHandle<eyre::Result<()>>> {
+    let routes = Arc::new(Routes::new(&config));
+    let (http_listener, router) = crate::setup(
+        Arc::new(RwLock::new(config)),
+        Arc::new(RwLock::new(Default::default())),
+        Arc::new(Drawbridge::new(routes, fallbacks)),
+    )
+    .await?;
 
     let handle = tokio::spawn(async {
-        axum::serve(http_listener, router).await?;
+        axum::serve(
+            http_listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await?;
 
         Ok(())
     });
@@ -1,10 +1,15 @@
 use color_eyre::eyre;
-use reqwest::RequestBuilder;
+use reqwest::{redirect::Policy, RequestBuilder};
 
 use super::TEST_INCIPIT_PORT;
 
+/// Builds a request to incipit for `host`. Redirects aren't followed, since they would leave
+/// incipit.
 pub fn builder(host: &str, path: &str) -> RequestBuilder {
-    let client = reqwest::Client::new();
+    let client = reqwest::Client::builder()
+        .redirect(Policy::none())
+        .build()
+        .expect("Client should be valid");
 
     let path = path.trim_start_matches('/');
 
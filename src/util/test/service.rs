@@ -34,7 +34,11 @@ type StoppedService = Service<()>;
 impl StoppedService {
     pub async fn start(self) -> eyre::Result<Service> {
         let server = Server::start(
-            ([127, 0, 0, 1], self.config.port).into(),
+            (
+                [127, 0, 0, 1],
+                self.config.port.expect("Mock services have a port"),
+            )
+                .into(),
             self.handler.inner(),
         )
         .await?;
@@ -51,11 +55,10 @@ fn service1() -> StoppedService {
     Service {
         handler: Handler::Simple(|_| Ok("Hello world".into())),
         config: ServiceConfig {
-            port: 1234,
+            port: Some(1234),
             host: "service0.example.com".into(),
             name: "service0".into(),
-            repo: None,
-            command: None,
+            ..Default::default()
         },
         server: (),
     }
@@ -65,11 +68,10 @@ fn service2() -> StoppedService {
     Service {
         handler: Handler::Simple(|path| Ok(format!("Hello path: {path}"))),
         config: ServiceConfig {
-            port: 9423,
+            port: Some(9423),
             host: "service1.example.com".into(),
             name: "service1".into(),
-            repo: None,
-            command: None,
+            ..Default::default()
         },
         server: (),
     }
@@ -83,11 +85,10 @@ fn service3() -> StoppedService {
             _ => Err(404),
         }),
         config: ServiceConfig {
-            port: 6969,
+            port: Some(6969),
             host: "service2.example.com".into(),
             name: "service2".into(),
-            repo: None,
-            command: None,
+            ..Default::default()
         },
         server: (),
     }
@@ -101,11 +102,10 @@ fn service_websockets() -> StoppedService {
             _ => Err(404),
         }),
         config: ServiceConfig {
-            port: 4455,
+            port: Some(4455),
             host: "websockets.example.com".into(),
             name: "websocket_service".into(),
-            repo: None,
-            command: None,
+            ..Default::default()
         },
         server: (),
     }